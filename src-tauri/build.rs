@@ -15,6 +15,13 @@ fn main() {
                     "localkey_verify",
                     "privkey_read",
                     "check_connection",
+                    "host_key_accept",
+                    "settings_read",
+                    "settings_write",
+                    "pool_list",
+                    "pool_drop",
+                    "capabilities",
+                    "monitor_status",
                     "app_ssh_key_path",
                     "app_ssh_pubkey",
                     "ssh_key_dir",
@@ -22,12 +29,20 @@ fn main() {
             )
             .plugin(
                 "remote-command",
-                InlinedPlugin::new().commands(&["exec", "spawn"]),
+                InlinedPlugin::new().commands(&[
+                    "exec",
+                    "exec_argv",
+                    "exec_many",
+                    "spawn",
+                    "captures",
+                    "stop_capture",
+                ]),
             )
             .plugin(
                 "remote-shell",
-                InlinedPlugin::new()
-                    .commands(&["open", "close", "write", "resize", "screen", "list"]),
+                InlinedPlugin::new().commands(&[
+                    "open", "attach", "close", "write", "ack", "resize", "screen", "list",
+                ]),
             )
             .plugin(
                 "remote-file",
@@ -41,6 +56,20 @@ fn main() {
             .plugin(
                 "local-file",
                 InlinedPlugin::new().commands(&["checksum", "remove", "copy", "temp_path"]),
+            )
+            .plugin(
+                "port-forward",
+                InlinedPlugin::new().commands(&[
+                    "start",
+                    "start_reverse",
+                    "start_socks",
+                    "list",
+                    "stop",
+                ]),
+            )
+            .plugin(
+                "luna",
+                InlinedPlugin::new().commands(&["call", "subscribe"]),
             ),
     )
     .expect("failed to run tauri-build");
@@ -90,6 +90,18 @@ pub fn conf_dir<R: Runtime>(app: &AppHandle<R>) -> Option<PathBuf> {
     }
 }
 
+/// The directory command output is captured into. Capture file names come
+/// from the webview, so captures never go anywhere else.
+pub fn capture_dir<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf, Error> {
+    let dir = app
+        .path()
+        .app_log_dir()
+        .map_err(|_| Error::bad_config())?
+        .join("captures");
+    create_dir_all(&dir)?;
+    Ok(dir)
+}
+
 /// The app's own SSH key, the one it offers to a device it sets up.
 pub trait GetAppSshKeyDir {
     fn get_app_ssh_key_path(&self) -> Result<PathBuf, Error>;
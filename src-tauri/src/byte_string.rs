@@ -25,6 +25,15 @@ impl ByteString {
             }
         }
     }
+
+    /// Like [`ByteString::parse`], but replaces invalid UTF-8 with U+FFFD
+    /// instead of failing, for output of commands that may print anything.
+    pub fn parse_lossy(raw: &[u8], encoding: Encoding) -> ByteString {
+        match encoding {
+            Encoding::Binary => ByteString::Binary(raw.to_vec()),
+            Encoding::String => ByteString::String(String::from_utf8_lossy(raw).into_owned()),
+        }
+    }
 }
 
 impl AsRef<[u8]> for ByteString {
@@ -0,0 +1,93 @@
+//! Building shell command lines for devices out of separate arguments.
+//!
+//! The SSH exec request takes one string that the device's shell parses, so
+//! every argument has to be quoted to reach the program unchanged. webOS ships
+//! BusyBox `sh`, so only POSIX quoting is used.
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::error::Error;
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct CommandLine {
+    pub argv: Vec<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    pub cwd: Option<String>,
+}
+
+impl CommandLine {
+    /// The command line for the device's shell. The program replaces the shell
+    /// with `exec`, so signals sent to the channel reach the program itself.
+    pub fn to_shell(&self) -> Result<String, Error> {
+        if self.argv.is_empty() {
+            return Err(Error::new("Command has no arguments"));
+        }
+        let mut parts = Vec::<String>::new();
+        if let Some(cwd) = &self.cwd {
+            parts.push(format!("cd -- {} &&", quote(cwd)));
+        }
+        if !self.env.is_empty() {
+            let mut export = String::from("export");
+            for (name, value) in &self.env {
+                if !is_name(name) {
+                    return Err(Error::new(format!(
+                        "Invalid environment variable name {name:?}"
+                    )));
+                }
+                export.push_str(&format!(" {name}={}", quote(value)));
+            }
+            export.push_str(" &&");
+            parts.push(export);
+        }
+        parts.push(String::from("exec"));
+        parts.extend(self.argv.iter().map(|arg| quote(arg)));
+        Ok(parts.join(" "))
+    }
+}
+
+/// Wraps `value` in single quotes, the only quoting in which the shell treats
+/// every character literally. A single quote inside ends the quoted part, adds
+/// an escaped quote, and starts a new quoted part.
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command_line::CommandLine;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_to_shell() {
+        let command = CommandLine {
+            argv: vec![String::from("ls"), String::from("it's here")],
+            env: BTreeMap::from([(String::from("LANG"), String::from("C"))]),
+            cwd: Some(String::from("/media/developer")),
+        };
+        assert_eq!(
+            r"cd -- '/media/developer' && export LANG='C' && exec 'ls' 'it'\''s here'",
+            command.to_shell().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_bad_env_name() {
+        let command = CommandLine {
+            argv: vec![String::from("true")],
+            env: BTreeMap::from([(String::from("A;B"), String::from(""))]),
+            cwd: None,
+        };
+        assert!(command.to_shell().is_err());
+    }
+}
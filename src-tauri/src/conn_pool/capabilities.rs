@@ -0,0 +1,231 @@
+//! What a device can do, found out once the first time anyone asks a session
+//! of its pool, so callers can pick a way that works instead of trying each.
+
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use ares_connection_lib::session::SshConnection;
+use libssh_rs::Sftp;
+use serde::Serialize;
+
+use crate::byte_string::Encoding;
+use crate::conn_pool::{DeviceConnection, ExecOptions, ExecuteCommand};
+use crate::error::Error;
+
+/// One line of `key=value` per fact. Anything the device lacks is left out.
+const PROBE_SCRIPT: &str = r#"
+echo "kernel=$(uname -r)"
+command -v luna-send >/dev/null && echo luna-send=1
+command -v luna-send-pub >/dev/null && echo luna-send-pub=1
+if ls --version 2>/dev/null | grep -q coreutils; then
+  echo userland=coreutils
+elif command -v busybox >/dev/null; then
+  echo userland=busybox
+fi
+webos=$(nyx-cmd OSInfo query webos_release 2>/dev/null)
+[ -n "$webos" ] && echo "webos=$webos"
+[ -d /media/developer/apps/usr/palm/applications/org.webosbrew.hbchannel ] && echo hbchannel=1
+true
+"#;
+
+/// How long the probe may take before the device is left unprobed.
+const PROBE_SCRIPT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Shared by all sessions of a pool, and `None` until a probe succeeds.
+pub(crate) type SharedCapabilities = Arc<Mutex<Option<DeviceCapabilities>>>;
+
+/// Devices found without SFTP, by name and address. Pools come and go with
+/// every settings change, so this outlives them until the app exits.
+static NO_SFTP: Mutex<BTreeSet<(String, String, u16)>> = Mutex::new(BTreeSet::new());
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceCapabilities {
+    /// Whether the SFTP subsystem starts.
+    pub sftp: bool,
+    pub luna_send: bool,
+    pub luna_send_pub: bool,
+    pub userland: Option<Userland>,
+    /// Kernel release, as `uname -r` prints it.
+    pub kernel: Option<String>,
+    /// webOS release, such as `6.3.1`.
+    pub webos_version: Option<String>,
+    /// Whether Homebrew Channel is installed.
+    pub homebrew_channel: bool,
+}
+
+/// Which implementation the common command line tools come from, which
+/// decides the options they take.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum Userland {
+    Busybox,
+    Coreutils,
+}
+
+impl DeviceCapabilities {
+    pub(super) fn probe(conn: &DeviceConnection) -> Result<DeviceCapabilities, Error> {
+        let output = conn.execute_command_with(
+            PROBE_SCRIPT,
+            None,
+            Encoding::String,
+            ExecOptions {
+                timeout: Some(PROBE_SCRIPT_TIMEOUT),
+                ..ExecOptions::default()
+            },
+        )?;
+        let mut capabilities = Self::parse(&String::from_utf8_lossy(output.stdout.as_ref()));
+        capabilities.sftp = !conn.known_without_sftp()
+            && conn
+                .sftp()
+                .inspect_err(|e| {
+                    log::info!("{conn:?} has no SFTP: {e:?}");
+                    conn.record_no_sftp();
+                })
+                .is_ok();
+        Ok(capabilities)
+    }
+
+    fn parse(output: &str) -> DeviceCapabilities {
+        let mut capabilities = DeviceCapabilities::default();
+        for (key, value) in output.lines().filter_map(|line| line.split_once('=')) {
+            let value = value.trim();
+            match key {
+                "kernel" if !value.is_empty() => capabilities.kernel = Some(value.to_string()),
+                "luna-send" => capabilities.luna_send = true,
+                "luna-send-pub" => capabilities.luna_send_pub = true,
+                "userland" => {
+                    capabilities.userland = match value {
+                        "busybox" => Some(Userland::Busybox),
+                        "coreutils" => Some(Userland::Coreutils),
+                        _ => None,
+                    }
+                }
+                "webos" => capabilities.webos_version = Some(value.to_string()),
+                "hbchannel" => capabilities.homebrew_channel = true,
+                _ => {}
+            }
+        }
+        capabilities
+    }
+}
+
+impl DeviceConnection {
+    /// What the device can do, probed on this session unless a session of
+    /// the pool already did. A failed probe leaves the capabilities unknown
+    /// until the next call tries again.
+    pub fn capabilities(&self) -> Option<DeviceCapabilities> {
+        if let Some(capabilities) = self.cached_capabilities() {
+            return Some(capabilities);
+        }
+        // Not locked while the probe runs, so one slow device doesn't hold up
+        // everyone asking. Callers that race probe twice, and keep the first.
+        match DeviceCapabilities::probe(self) {
+            Ok(probed) => {
+                log::info!("{self:?} has {probed:?}");
+                let mut capabilities = self
+                    .capabilities
+                    .lock()
+                    .expect("Failed to lock DeviceConnection::capabilities");
+                Some(capabilities.get_or_insert(probed).clone())
+            }
+            Err(e) => {
+                log::warn!("{self:?} failed to probe capabilities: {e:?}");
+                None
+            }
+        }
+    }
+
+    /// What the device can do, if a session of its pool found out.
+    pub(crate) fn cached_capabilities(&self) -> Option<DeviceCapabilities> {
+        self.capabilities
+            .lock()
+            .expect("Failed to lock DeviceConnection::capabilities")
+            .clone()
+    }
+
+    /// An SFTP session, or `None` if the device has none and files have to
+    /// go over exec channels. A device found without SFTP here is remembered,
+    /// so later calls don't try again, not even on a new pool.
+    pub fn try_sftp(&self) -> Result<Option<Sftp>, Error> {
+        if !self.supports_sftp() {
+            return Ok(None);
+        }
+        let e = match self.sftp() {
+            Ok(sftp) => return Ok(Some(sftp)),
+            Err(e) => Error::from(e),
+        };
+        // A dead session fails to start SFTP as well, but that says nothing
+        // about the device.
+        if !self.is_connected() {
+            return Err(e);
+        }
+        log::warn!("{self:?} failed to start SFTP, using exec channels: {e:?}");
+        // A probe that runs later finds the device in the list by itself.
+        self.record_no_sftp();
+        if let Some(capabilities) = self
+            .capabilities
+            .lock()
+            .expect("Failed to lock DeviceConnection::capabilities")
+            .as_mut()
+        {
+            capabilities.sftp = false;
+        }
+        Ok(None)
+    }
+
+    pub(super) fn known_without_sftp(&self) -> bool {
+        no_sftp().contains(&self.sftp_key())
+    }
+
+    fn record_no_sftp(&self) {
+        no_sftp().insert(self.sftp_key());
+    }
+
+    fn sftp_key(&self) -> (String, String, u16) {
+        (
+            self.device.name.clone(),
+            self.device.host.clone(),
+            self.device.port,
+        )
+    }
+}
+
+fn no_sftp() -> MutexGuard<'static, BTreeSet<(String, String, u16)>> {
+    NO_SFTP.lock().expect("Failed to lock NO_SFTP")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conn_pool::capabilities::{DeviceCapabilities, Userland};
+    use crate::conn_pool::DeviceConnection;
+    use crate::tests::common::{test_device, SshContainer, TempDir};
+
+    #[test]
+    fn parse_webos() {
+        let capabilities = DeviceCapabilities::parse(
+            "kernel=4.4.84-234.gld4.lgtv\nluna-send=1\nluna-send-pub=1\nuserland=busybox\n\
+            webos=6.3.1\nhbchannel=1\n",
+        );
+        assert_eq!(Some("4.4.84-234.gld4.lgtv"), capabilities.kernel.as_deref());
+        assert!(capabilities.luna_send && capabilities.luna_send_pub);
+        assert_eq!(Some(Userland::Busybox), capabilities.userland);
+        assert_eq!(Some("6.3.1"), capabilities.webos_version.as_deref());
+        assert!(capabilities.homebrew_channel);
+    }
+
+    #[test]
+    fn probe_sshd() {
+        let sshd = SshContainer::new();
+        let port = sshd.wait();
+        let device = test_device(port);
+        let conf_dir = TempDir::new();
+        let conn = DeviceConnection::new(device, None, Some(conf_dir.path()))
+            .expect("Failed to create connection");
+        let capabilities = DeviceCapabilities::probe(&conn).expect("Failed to probe");
+        assert!(capabilities.kernel.is_some());
+        assert!(!capabilities.luna_send);
+        assert_eq!(None, capabilities.webos_version);
+        assert!(!capabilities.homebrew_channel);
+    }
+}
@@ -2,7 +2,34 @@ use crate::byte_string::{ByteString, Encoding};
 use crate::conn_pool::DeviceConnection;
 use crate::error::Error;
 use crate::plugins::cmd::ExecOutput;
-use std::io::{Read, Write};
+use crate::session_manager::ProcResult;
+use libssh_rs::Channel;
+use std::io::Write;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// How long the loop sleeps when neither stream had anything to read.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How much the loop reads from each stream, or writes to stdin, before it
+/// moves on. A command that writes without pause must not keep the loop on
+/// one stream, or the deadline is never checked.
+const CHUNK_PER_PASS: usize = 64 * 1024;
+
+/// Limits on one command. The default sets none.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ExecOptions {
+    /// How long the command may run before it is killed and the call fails
+    /// with [`Error::Timeout`].
+    pub timeout: Option<Duration>,
+    /// How many bytes of each stream are kept. Anything past that is read and
+    /// dropped, so the command still runs to the end, and the output is marked
+    /// as truncated.
+    pub max_output: Option<usize>,
+    /// Return the output of a command that fails instead of
+    /// [`Error::ExitStatus`], with [`ExecOutput::result`] telling how it ended.
+    pub allow_failure: bool,
+}
 
 pub trait ExecuteCommand {
     fn execute_command(
@@ -10,53 +37,191 @@ pub trait ExecuteCommand {
         command: &str,
         stdin: Option<&ByteString>,
         encoding: Encoding,
+    ) -> Result<ExecOutput, Error> {
+        self.execute_command_with(command, stdin, encoding, ExecOptions::default())
+    }
+
+    fn execute_command_with(
+        &self,
+        command: &str,
+        stdin: Option<&ByteString>,
+        encoding: Encoding,
+        options: ExecOptions,
     ) -> Result<ExecOutput, Error>;
 }
 
 impl ExecuteCommand for DeviceConnection {
-    fn execute_command(
+    fn execute_command_with(
         &self,
         command: &str,
         stdin: Option<&ByteString>,
         encoding: Encoding,
+        options: ExecOptions,
     ) -> Result<ExecOutput, Error> {
         let ch = self.new_channel()?;
         ch.open_session()?;
         ch.request_exec(command)?;
-        if let Some(stdin) = stdin.clone() {
-            ch.stdin().write_all(stdin.as_ref())?;
-            ch.send_eof()?;
+        let mut stdin = stdin.map(|stdin| Feed::new(stdin.as_ref()));
+        let started_at = Instant::now();
+        let deadline = options.timeout.map(|timeout| started_at + timeout);
+        let mut stdout = Capture::new(options.max_output);
+        let mut stderr = Capture::new(options.max_output);
+        let mut buf = [0; 8192];
+        loop {
+            // Checked before the reads, so the loop only ends on a pass that
+            // found nothing left of what arrived ahead of EOF.
+            let eof = ch.is_eof() || ch.is_closed();
+            // Both streams share one flow control window. Reading one to its
+            // end before touching the other stalls a command that fills the
+            // other, so both are read on every pass. stdin is written in the
+            // same loop, as a command may not read more until its output is.
+            let mut busy = stdout.drain(&ch, false, &mut buf)?;
+            busy |= stderr.drain(&ch, true, &mut buf)?;
+            if eof && !busy {
+                break;
+            }
+            // A command that ended no longer takes input.
+            if let Some(feed) = stdin.as_mut().filter(|_| !eof) {
+                busy |= feed.write(&ch)?;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                log::warn!("Killing `{command}`, which ran past {:?}", options.timeout);
+                // Some servers ignore signals. Closing the channel still
+                // breaks the command's pipes, which ends most commands.
+                ch.request_send_signal("KILL").unwrap_or(());
+                ch.close()?;
+                self.mark_last_ok();
+                return Err(Error::Timeout);
+            }
+            if !busy {
+                sleep(POLL_INTERVAL);
+            }
         }
-        let mut stdout = Vec::<u8>::new();
-        ch.stdout().read_to_end(&mut stdout)?;
-        let mut stderr = Vec::<u8>::new();
-        ch.stderr().read_to_end(&mut stderr)?;
-        let exit_code = ch.get_exit_status().unwrap_or(0);
+        let result = ProcResult::from_channel(&ch);
+        let duration = started_at.elapsed();
         ch.close()?;
         self.mark_last_ok();
-        if exit_code != 0 {
-            return Err(Error::ExitStatus {
-                message: "".to_string(),
-                command: command.to_string(),
-                exit_code,
-                stderr,
-                unhandled: true,
-            });
+        if !options.allow_failure {
+            let (exit_code, signal) = match &result {
+                ProcResult::Exit { status } => (*status, None),
+                ProcResult::Signal { signal, .. } => (-1, signal.clone()),
+                // Old servers close the channel without reporting anything, so
+                // this can't be told apart from success.
+                ProcResult::Closed => (0, None),
+            };
+            if exit_code != 0 {
+                return Err(Error::ExitStatus {
+                    message: "".to_string(),
+                    command: command.to_string(),
+                    exit_code,
+                    signal,
+                    stderr: stderr.data,
+                    unhandled: true,
+                });
+            }
         }
         Ok(ExecOutput {
-            stdout: ByteString::parse(&stdout, encoding).unwrap(),
-            stderr: ByteString::parse(&stderr, encoding).unwrap(),
+            stdout: ByteString::parse_lossy(&stdout.data, encoding),
+            stderr: ByteString::parse_lossy(&stderr.data, encoding),
+            truncated: stdout.truncated || stderr.truncated,
+            result,
+            duration: duration.as_secs_f64(),
         })
     }
 }
 
+/// One output stream of a command, cut off at the size limit.
+struct Capture {
+    data: Vec<u8>,
+    limit: Option<usize>,
+    truncated: bool,
+}
+
+impl Capture {
+    fn new(limit: Option<usize>) -> Self {
+        Capture {
+            data: Vec::new(),
+            limit,
+            truncated: false,
+        }
+    }
+
+    /// Reads what the stream has buffered, up to [`CHUNK_PER_PASS`], without
+    /// waiting for more. Returns whether there was anything.
+    fn drain(&mut self, ch: &Channel, is_stderr: bool, buf: &mut [u8]) -> Result<bool, Error> {
+        let mut total = 0;
+        while total < CHUNK_PER_PASS {
+            let size = match ch.read_timeout(buf, is_stderr, Some(Duration::ZERO)) {
+                Ok(size) => size,
+                Err(libssh_rs::Error::TryAgain) => 0,
+                Err(e) => return Err(Error::from(e)),
+            };
+            if size == 0 {
+                break;
+            }
+            total += size;
+            self.push(&buf[..size]);
+        }
+        Ok(total > 0)
+    }
+
+    fn push(&mut self, chunk: &[u8]) {
+        let room = match self.limit {
+            Some(limit) => limit.saturating_sub(self.data.len()),
+            None => chunk.len(),
+        };
+        if chunk.len() > room {
+            self.truncated = true;
+        }
+        self.data.extend_from_slice(&chunk[..chunk.len().min(room)]);
+    }
+}
+
+/// Input for a command, written as the device makes room for it.
+struct Feed<'a> {
+    data: &'a [u8],
+    eof_sent: bool,
+}
+
+impl<'a> Feed<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Feed {
+            data,
+            eof_sent: false,
+        }
+    }
+
+    /// Writes as much as the channel window takes, up to [`CHUNK_PER_PASS`],
+    /// and sends EOF once all of it is written. Returns whether it wrote
+    /// anything.
+    fn write(&mut self, ch: &Channel) -> Result<bool, Error> {
+        if self.eof_sent {
+            return Ok(false);
+        }
+        if self.data.is_empty() {
+            ch.send_eof()?;
+            self.eof_sent = true;
+            return Ok(true);
+        }
+        let size = self.data.len().min(ch.window_size()).min(CHUNK_PER_PASS);
+        if size == 0 {
+            return Ok(false);
+        }
+        ch.stdin().write_all(&self.data[..size])?;
+        self.data = &self.data[size..];
+        Ok(true)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::byte_string::{ByteString, Encoding};
-    use crate::conn_pool::{DeviceConnection, ExecuteCommand};
-    use crate::device_manager::Device;
+    use crate::conn_pool::{DeviceConnection, ExecOptions, ExecuteCommand};
+    use crate::device_manager::{Device, DeviceSettings};
     use crate::error::Error;
-    use crate::tests::common::SshContainer;
+    use crate::session_manager::ProcResult;
+    use crate::tests::common::{test_device, SshAgent, SshContainer, TempDir};
+    use std::time::Duration;
 
     #[test]
     fn execute_command_timeout() {
@@ -74,7 +239,7 @@ mod test {
 "#,
         )
         .unwrap();
-        let conn = DeviceConnection::new(device, None);
+        let conn = DeviceConnection::new(device, None, None);
         assert_eq!(Error::Timeout, conn.unwrap_err())
     }
 
@@ -87,7 +252,9 @@ mod test {
             \"port\": {port},\"username\": \"root\"}}"
         ))
         .unwrap();
-        let err = DeviceConnection::new(device, None).expect_err("Should have failed");
+        let conf_dir = TempDir::new();
+        let err = DeviceConnection::new(device, None, Some(conf_dir.path()))
+            .expect_err("Should have failed");
         assert!(
             matches!(err, Error::Authorization { message } if message == "Host needs authorization")
         );
@@ -102,7 +269,9 @@ mod test {
             \"port\": {port},\"username\": \"root\",\"password\": \"youshallnotpass\"}}"
         ))
         .unwrap();
-        let err = DeviceConnection::new(device, None).expect_err("Should have failed");
+        let conf_dir = TempDir::new();
+        let err = DeviceConnection::new(device, None, Some(conf_dir.path()))
+            .expect_err("Should have failed");
         assert!(matches!(err, Error::Authorization { message } if message == "Bad SSH password"));
     }
 
@@ -115,7 +284,9 @@ mod test {
             \"port\": {port},\"username\": \"root\",\"password\": \"alpine\"}}"
         ))
         .unwrap();
-        let conn = DeviceConnection::new(device, None).expect("Failed to create connection");
+        let conf_dir = TempDir::new();
+        let conn = DeviceConnection::new(device, None, Some(conf_dir.path()))
+            .expect("Failed to create connection");
         let output = conn
             .execute_command("whoami", None, Encoding::String)
             .expect("Failed to execute command");
@@ -131,7 +302,35 @@ mod test {
             \"port\": {port},\"username\": \"root\",\"privateKey\": {{\"openSsh\": \"id_root\"}}}}"
         ))
         .unwrap();
-        let conn = DeviceConnection::new(device, Some(&SshContainer::fixture_path("keys", false)))
+        let conf_dir = TempDir::new();
+        let conn = DeviceConnection::new(
+            device,
+            Some(&SshContainer::fixture_path("keys", false)),
+            Some(conf_dir.path()),
+        )
+        .expect("Failed to create connection");
+        let output = conn
+            .execute_command("whoami", None, Encoding::String)
+            .expect("Failed to execute command");
+        assert_eq!(b"root\n", output.stdout.as_ref());
+    }
+
+    #[test]
+    fn execute_command_whoami_agent() {
+        let sshd = SshContainer::new();
+        let port = sshd.wait();
+        let agent = SshAgent::new();
+        let mut device = test_device(port);
+        device.password = None;
+        let conf_dir = TempDir::new();
+        DeviceSettings {
+            ssh_agent: Some(true),
+            ssh_agent_socket: Some(agent.socket.to_string_lossy().to_string()),
+            ..DeviceSettings::default()
+        }
+        .save(conf_dir.path(), "test")
+        .unwrap();
+        let conn = DeviceConnection::new(device, None, Some(conf_dir.path()))
             .expect("Failed to create connection");
         let output = conn
             .execute_command("whoami", None, Encoding::String)
@@ -148,7 +347,9 @@ mod test {
             \"port\": {port},\"username\": \"root\",\"password\": \"alpine\"}}"
         ))
         .unwrap();
-        let conn = DeviceConnection::new(device, None).expect("Failed to create connection");
+        let conf_dir = TempDir::new();
+        let conn = DeviceConnection::new(device, None, Some(conf_dir.path()))
+            .expect("Failed to create connection");
         let err = conn
             .execute_command(
                 "false",
@@ -158,4 +359,139 @@ mod test {
             .expect_err("Should have failed");
         assert!(matches!(err, Error::ExitStatus { exit_code, .. } if exit_code == 1));
     }
+
+    #[test]
+    fn execute_command_killed() {
+        let sshd = SshContainer::new();
+        let port = sshd.wait();
+        let device = test_device(port);
+        let conf_dir = TempDir::new();
+        let conn = DeviceConnection::new(device, None, Some(conf_dir.path()))
+            .expect("Failed to create connection");
+        let err = conn
+            .execute_command("printf '\\377'; kill -KILL $$", None, Encoding::String)
+            .expect_err("Should have failed");
+        assert!(matches!(err, Error::ExitStatus { signal: Some(ref s), .. } if s == "KILL"));
+        let output = conn
+            .execute_command_with(
+                "printf '\\377'; kill -KILL $$",
+                None,
+                Encoding::String,
+                ExecOptions {
+                    allow_failure: true,
+                    ..ExecOptions::default()
+                },
+            )
+            .expect("Failed to execute command");
+        assert_eq!("\u{FFFD}".as_bytes(), output.stdout.as_ref());
+        assert_eq!(
+            ProcResult::Signal {
+                signal: Some(String::from("KILL")),
+                core_dumped: false,
+            },
+            output.result
+        );
+    }
+
+    #[test]
+    fn execute_command_interleaved() {
+        let sshd = SshContainer::new();
+        let port = sshd.wait();
+        let device = test_device(port);
+        let conf_dir = TempDir::new();
+        let conn = DeviceConnection::new(device, None, Some(conf_dir.path()))
+            .expect("Failed to create connection");
+        let output = conn
+            .execute_command(
+                "head -c 4194304 /dev/zero >&2; echo done",
+                None,
+                Encoding::Binary,
+            )
+            .expect("Failed to execute command");
+        assert_eq!(b"done\n", output.stdout.as_ref());
+        assert_eq!(4194304, output.stderr.as_ref().len());
+    }
+
+    #[test]
+    fn execute_command_truncated() {
+        let sshd = SshContainer::new();
+        let port = sshd.wait();
+        let device = test_device(port);
+        let conf_dir = TempDir::new();
+        let conn = DeviceConnection::new(device, None, Some(conf_dir.path()))
+            .expect("Failed to create connection");
+        let output = conn
+            .execute_command_with(
+                "head -c 65536 /dev/zero",
+                None,
+                Encoding::Binary,
+                ExecOptions {
+                    max_output: Some(1000),
+                    ..ExecOptions::default()
+                },
+            )
+            .expect("Failed to execute command");
+        assert_eq!(1000, output.stdout.as_ref().len());
+        assert!(output.truncated);
+    }
+
+    #[test]
+    fn execute_command_deadline() {
+        let sshd = SshContainer::new();
+        let port = sshd.wait();
+        let device = test_device(port);
+        let conf_dir = TempDir::new();
+        let conn = DeviceConnection::new(device, None, Some(conf_dir.path()))
+            .expect("Failed to create connection");
+        let err = conn
+            .execute_command_with(
+                "sleep 30",
+                None,
+                Encoding::String,
+                ExecOptions {
+                    timeout: Some(Duration::from_secs(1)),
+                    ..ExecOptions::default()
+                },
+            )
+            .expect_err("Should have timed out");
+        assert_eq!(Error::Timeout, err);
+    }
+
+    #[test]
+    fn execute_command_deadline_flood() {
+        let sshd = SshContainer::new();
+        let port = sshd.wait();
+        let device = test_device(port);
+        let conf_dir = TempDir::new();
+        let conn = DeviceConnection::new(device, None, Some(conf_dir.path()))
+            .expect("Failed to create connection");
+        let err = conn
+            .execute_command_with(
+                "yes",
+                None,
+                Encoding::Binary,
+                ExecOptions {
+                    timeout: Some(Duration::from_secs(1)),
+                    max_output: Some(1000),
+                    ..ExecOptions::default()
+                },
+            )
+            .expect_err("Should have timed out");
+        assert_eq!(Error::Timeout, err);
+    }
+
+    #[test]
+    fn execute_command_large_stdin() {
+        let sshd = SshContainer::new();
+        let port = sshd.wait();
+        let device = test_device(port);
+        let conf_dir = TempDir::new();
+        let conn = DeviceConnection::new(device, None, Some(conf_dir.path()))
+            .expect("Failed to create connection");
+        let input = ByteString::Binary(vec![b'x'; 4194304]);
+        let output = conn
+            .execute_command("cat", Some(&input), Encoding::Binary)
+            .expect("Failed to execute command");
+        assert_eq!(4194304, output.stdout.as_ref().len());
+    }
 }
@@ -1,37 +1,90 @@
 use std::fmt::{Debug, Formatter};
 use std::io::Read;
+use std::net::TcpStream;
 use std::ops::{Deref, DerefMut};
 use std::path::Path;
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
-use ares_connection_lib::session::{authenticate, connect, SshConnection};
-use libssh_rs::Session;
+use ares_connection_lib::session::{authenticate, SshConnection};
+use libssh_rs::{AuthMethods, AuthStatus, PublicKeyHashType, Session, SshOption};
 use regex::Regex;
 use uuid::Uuid;
 
-use crate::conn_pool::{DeviceConnection, DeviceConnectionUserInfo, Id};
+use crate::conn_pool::capabilities::SharedCapabilities;
+use crate::conn_pool::{jump, prompt, DeviceConnection, DeviceConnectionUserInfo, Id};
+use crate::device_manager::known_hosts::KnownHosts;
 use crate::device_manager::privkey::PrivateKeyExt;
-use crate::device_manager::{Device, DeviceFileTransfer};
+use crate::device_manager::{Device, DeviceFileTransfer, DeviceSettings};
 use crate::error::Error;
 
+/// The limit on every blocking libssh call, the same one the shared `connect`
+/// sets, unless the device's `connect_timeout` says otherwise.
+pub(crate) const SESSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the liveness probe waits for the device to answer before the
+/// session is given up as dead.
+pub(crate) const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Algorithms to offer, the same ones the shared `connect` offers. Older webOS
+/// images only speak some of the legacy ones.
+const KEY_EXCHANGE: &[&str] = &[
+    "curve25519-sha256",
+    "curve25519-sha256@libssh.org",
+    "ecdh-sha2-nistp256",
+    "ecdh-sha2-nistp384",
+    "ecdh-sha2-nistp521",
+    "diffie-hellman-group18-sha512",
+    "diffie-hellman-group16-sha512",
+    "diffie-hellman-group-exchange-sha256",
+    "diffie-hellman-group14-sha256",
+    "diffie-hellman-group1-sha1",
+    "diffie-hellman-group14-sha1",
+];
+const HMAC: &[&str] = &[
+    "hmac-sha2-256-etm@openssh.com",
+    "hmac-sha2-512-etm@openssh.com",
+    "hmac-sha2-256",
+    "hmac-sha2-512",
+    "hmac-sha1-96",
+    "hmac-sha1",
+    "hmac-md5",
+];
+const KEY_TYPES: &[&str] = &[
+    "ssh-ed25519",
+    "ecdsa-sha2-nistp521",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp256",
+    "rsa-sha2-512",
+    "rsa-sha2-256",
+    "ssh-rsa",
+];
+
+/// Host keys are checked against [`KnownHosts`], never against OpenSSH's files.
+#[cfg(windows)]
+const NO_KNOWN_HOSTS: &str = "NUL";
+#[cfg(not(windows))]
+const NO_KNOWN_HOSTS: &str = "/dev/null";
+
 impl DeviceConnection {
-    pub(crate) fn new(device: Device, ssh_dir: Option<&Path>) -> Result<DeviceConnection, Error> {
-        let session = connect(&device)?;
-        // The key is read here, not by the shared code: this app resolves a key
-        // name against its own SSH directory, and falls back to the parent
-        // directory on mobile.
-        let key = device
-            .private_key
-            .as_ref()
-            .map(|k| k.read_content(ssh_dir))
-            .transpose()?;
-        authenticate(&session, &device, key.as_deref())?;
+    pub(crate) fn new(
+        device: Device,
+        ssh_dir: Option<&Path>,
+        conf_dir: Option<&Path>,
+    ) -> Result<DeviceConnection, Error> {
+        let settings = DeviceSettings::load(conf_dir, &device.name);
+        let session = login(&device, &settings, ssh_dir, conf_dir)?;
         let connection = DeviceConnection {
             id: Uuid::new_v4(),
             device: device.clone(),
             user: DeviceConnectionUserInfo::new(&session)?,
+            settings,
+            capabilities: SharedCapabilities::default(),
             session,
             last_ok: Mutex::new(true),
+            last_alive: Mutex::new(Instant::now()),
+            created_at: Instant::now(),
+            live: None,
         };
         log::info!("{:?} created", connection);
         Ok(connection)
@@ -49,8 +102,164 @@ impl DeviceConnection {
             .last_ok
             .lock()
             .expect("Failed to lock DeviceConnection::last_ok") = true;
+        self.mark_alive();
     }
 
+    /// How long since the device last answered on this connection.
+    pub(crate) fn idle_time(&self) -> Duration {
+        self.last_alive
+            .lock()
+            .expect("Failed to lock DeviceConnection::last_alive")
+            .elapsed()
+    }
+
+    /// Opens and closes a channel, the cheapest round trip that proves the
+    /// device is still answering. With a `timeout`, blocking calls give up
+    /// after it while the probe runs, so a device that went to sleep fails
+    /// fast instead of hanging. That timeout is set on the whole session, so
+    /// only the one holder of a session may pass it.
+    pub(crate) fn probe(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        if let Some(timeout) = timeout {
+            self.session.set_option(SshOption::Timeout(timeout))?;
+        }
+        let result = (|| {
+            let ch = self.session.new_channel()?;
+            ch.open_session()?;
+            ch.close()?;
+            Ok::<(), Error>(())
+        })();
+        if timeout.is_some() {
+            self.session
+                .set_option(SshOption::Timeout(self.settings.connect_timeout()))?;
+        }
+        result?;
+        self.mark_alive();
+        Ok(())
+    }
+
+    /// Notes that the device answered on this connection just now.
+    pub(crate) fn mark_alive(&self) {
+        *self
+            .last_alive
+            .lock()
+            .expect("Failed to lock DeviceConnection::last_alive") = Instant::now();
+    }
+}
+
+/// Connects, directly or through the jump host, checks the host key and
+/// authenticates.
+pub(super) fn login(
+    device: &Device,
+    settings: &DeviceSettings,
+    ssh_dir: Option<&Path>,
+    conf_dir: Option<&Path>,
+) -> Result<Session, Error> {
+    let started = Instant::now();
+    let socket = match &settings.jump_host {
+        Some(jump_host) => Some(jump::tunnel(jump_host, device, ssh_dir, conf_dir)?),
+        None => None,
+    };
+    let session = connect(device, settings, socket)?;
+    // Check the host key before sending any credentials. Without a config
+    // directory there is no key to check against, so the login stops here.
+    let conf_dir = conf_dir.ok_or_else(Error::bad_config)?;
+    let fingerprint = session
+        .get_server_public_key()?
+        .get_public_key_hash_hexa(PublicKeyHashType::Sha256)?;
+    KnownHosts::in_dir(conf_dir).verify(device, &fingerprint)?;
+    if settings.ssh_agent() && agent_login(&session, device, settings)? {
+        return Ok(session);
+    }
+    // The key is read here, not by the shared code: this app resolves a key
+    // name against its own SSH directory, and falls back to the parent
+    // directory on mobile.
+    let key = device
+        .private_key
+        .as_ref()
+        .map(|k| k.read_content(ssh_dir))
+        .transpose()?;
+    match authenticate(&session, device, key.as_deref()).map_err(Error::from) {
+        Ok(()) => Ok(session),
+        // Some images only offer keyboard-interactive, which the shared code
+        // doesn't do.
+        Err(Error::Authorization { .. })
+            if session
+                .userauth_list(Some(&device.username))?
+                .contains(AuthMethods::INTERACTIVE) =>
+        {
+            // The pool gives up on the checkout after `checkout_timeout`, so
+            // the answers must come with a connect's worth of time to spare.
+            let deadline = started + settings.checkout_timeout() - settings.connect_timeout();
+            prompt::login(&session, device, deadline)?;
+            Ok(session)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Offers the agent's identities, and returns whether the device accepted one.
+/// A missing agent is only logged, so the usual key and password still get
+/// their turn.
+fn agent_login(
+    session: &Session,
+    device: &Device,
+    settings: &DeviceSettings,
+) -> Result<bool, Error> {
+    if let Some(socket) = &settings.ssh_agent_socket {
+        session.set_option(SshOption::IdentityAgent(Some(socket.clone())))?;
+    }
+    match session.userauth_agent(Some(&device.username)) {
+        Ok(AuthStatus::Success) => Ok(true),
+        Ok(status) => {
+            log::info!("SSH agent login to {} ended with {status:?}", device.name);
+            Ok(false)
+        }
+        Err(e) => {
+            log::warn!("SSH agent login to {} failed: {e:?}", device.name);
+            Ok(false)
+        }
+    }
+}
+
+/// Opens the TCP connection, or takes over `socket`, and does the handshake.
+/// This is the shared `connect` with the device's settings on top: it takes
+/// none, and the timeout, compression and socket all have to be set before
+/// the handshake. Everything the shared one sets is set here too, so a device
+/// that connects with the CLI tools connects here. libssh keeps using the
+/// timeout for every blocking call after the handshake.
+fn connect(
+    device: &Device,
+    settings: &DeviceSettings,
+    socket: Option<TcpStream>,
+) -> Result<Session, Error> {
+    let session = Session::new()?;
+    // libssh closes the socket along with the session.
+    if let Some(socket) = socket {
+        #[cfg(unix)]
+        let raw = std::os::unix::io::IntoRawFd::into_raw_fd(socket);
+        #[cfg(windows)]
+        let raw = std::os::windows::io::IntoRawSocket::into_raw_socket(socket);
+        session.set_option(SshOption::Socket(raw))?;
+    }
+    session.set_option(SshOption::Timeout(settings.connect_timeout()))?;
+    session.set_option(SshOption::Hostname(device.host.clone()))?;
+    session.set_option(SshOption::Port(device.port))?;
+    session.set_option(SshOption::User(Some(device.username.clone())))?;
+    session.set_option(SshOption::KeyExchange(KEY_EXCHANGE.join(",")))?;
+    session.set_option(SshOption::HmacCS(HMAC.join(",")))?;
+    session.set_option(SshOption::HmacSC(HMAC.join(",")))?;
+    session.set_option(SshOption::HostKeys(KEY_TYPES.join(",")))?;
+    session.set_option(SshOption::PublicKeyAcceptedTypes(KEY_TYPES.join(",")))?;
+    session.set_option(SshOption::ProcessConfig(false))?;
+    session.set_option(SshOption::KnownHosts(Some(NO_KNOWN_HOSTS.to_string())))?;
+    session.set_option(SshOption::GlobalKnownHosts(Some(
+        NO_KNOWN_HOSTS.to_string(),
+    )))?;
+    if settings.compression() {
+        session.set_option(SshOption::Compression(String::from("yes")))?;
+    }
+    session.connect()?;
+    Ok(session)
 }
 
 impl SshConnection for DeviceConnection {
@@ -59,7 +268,10 @@ impl SshConnection for DeviceConnection {
     }
 
     fn supports_sftp(&self) -> bool {
-        !matches!(self.device.files, Some(DeviceFileTransfer::Stream))
+        if matches!(self.device.files, Some(DeviceFileTransfer::Stream)) {
+            return false;
+        }
+        !self.known_without_sftp() && self.cached_capabilities().is_none_or(|c| c.sftp)
     }
 
     fn is_root(&self) -> bool {
@@ -83,6 +295,11 @@ impl DerefMut for DeviceConnection {
 
 impl Drop for DeviceConnection {
     fn drop(&mut self) {
+        if let Some(live) = &self.live {
+            live.lock()
+                .expect("Failed to lock DeviceConnectionPool::live")
+                .remove(&self.id);
+        }
         log::info!(
             "Dropping {:?}, last_ok={}",
             self,
@@ -111,6 +328,13 @@ impl DeviceConnectionUserInfo {
         ch.stdout().read_to_string(&mut buf)?;
         let exit_code = ch.get_exit_status().unwrap_or(0);
         ch.close()?;
+        // Without PAM, sshd lets the login through and runs passwd in place of
+        // the command.
+        if buf.contains("password has expired") {
+            return Err(Error::PasswordChangeRequired {
+                message: buf.trim().to_string(),
+            });
+        }
         if exit_code != 0 {
             return Err(Error::Message {
                 message: format!("id command failed with exit code {}", exit_code),
@@ -0,0 +1,201 @@
+//! Reaching a device through another device, like OpenSSH's `ProxyJump`.
+//!
+//! libssh can't run a session over a channel of another session, only over a
+//! socket. So the tunnel is a `direct-tcpip` channel on the jump host, copied
+//! to and from one end of a loopback TCP connection, and the device's session
+//! gets the other end.
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use ares_device_lib::DeviceManager as SharedDeviceManager;
+use libssh_rs::{Channel, Session};
+
+use crate::conn_pool::connection::login;
+use crate::device_manager::{Device, DeviceSettings};
+use crate::error::Error;
+
+/// How long the copy loop waits on the local socket before it looks at the
+/// channel again.
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Bytes a tunnel carried each way.
+#[derive(Default, Debug)]
+pub(crate) struct Traffic {
+    /// From the socket to the device.
+    pub sent: AtomicU64,
+    /// From the device to the socket.
+    pub received: AtomicU64,
+}
+
+/// Logs in to the device named `jump_host` and opens a tunnel from it to
+/// `target`. The session on the jump host lives as long as the returned
+/// socket stays open.
+pub(super) fn tunnel(
+    jump_host: &str,
+    target: &Device,
+    ssh_dir: Option<&Path>,
+    conf_dir: Option<&Path>,
+) -> Result<TcpStream, Error> {
+    let (Some(ssh_dir), Some(conf_dir)) = (ssh_dir, conf_dir) else {
+        return Err(Error::bad_config());
+    };
+    let shared = SharedDeviceManager::with_dirs(conf_dir.to_path_buf(), ssh_dir.to_path_buf());
+    let Some(bastion) = shared.find_or_default(Some(jump_host))? else {
+        return Err(Error::new(format!(
+            "Jump host {jump_host} of {} is not a known device",
+            target.name
+        )));
+    };
+    let settings = DeviceSettings::load(Some(conf_dir), &bastion.name);
+    // Chains would need a loop check, and no setup here has needed one.
+    if settings.jump_host.is_some() {
+        return Err(Error::new(format!(
+            "Jump host {jump_host} has a jump host of its own, which is not supported"
+        )));
+    }
+    let session = login(&bastion, &settings, Some(ssh_dir), Some(conf_dir))?;
+    let channel = session.new_channel()?;
+    channel.open_forward(&target.host, target.port, "127.0.0.1", 0)?;
+    log::info!(
+        "Tunneling to {}:{} through {jump_host}",
+        target.host,
+        target.port
+    );
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let local = TcpStream::connect(listener.local_addr()?)?;
+    let (remote, peer) = listener.accept()?;
+    // Anything on this machine could have connected to the listener first.
+    if peer != local.local_addr()? {
+        return Err(Error::new("Unexpected connection to the tunnel socket"));
+    }
+    remote.set_read_timeout(Some(POLL_INTERVAL))?;
+    let name = format!("jump-{}", target.name);
+    thread::Builder::new().name(name.clone()).spawn(move || {
+        if let Err(e) = copy(&channel, remote, &Traffic::default()) {
+            log::warn!("{name} closed with {e:?}");
+        }
+        channel.close().unwrap_or(());
+        drop(session);
+    })?;
+    Ok(local)
+}
+
+/// Copies data both ways until either side closes, counting it in `traffic`.
+/// The socket needs a read timeout of [`POLL_INTERVAL`], which paces the loop
+/// while both sides are quiet.
+pub(crate) fn copy(
+    channel: &Channel,
+    mut socket: TcpStream,
+    traffic: &Traffic,
+) -> Result<(), Error> {
+    let mut buf = [0; 16384];
+    let mut waiting = true;
+    loop {
+        let size = match channel.read_timeout(&mut buf, false, Some(Duration::ZERO)) {
+            Ok(size) => size,
+            Err(libssh_rs::Error::TryAgain) => 0,
+            Err(e) => return Err(e.into()),
+        };
+        if size > 0 {
+            write_all(&mut socket, &buf[..size])?;
+            traffic.received.fetch_add(size as u64, Ordering::Relaxed);
+        } else if channel.is_eof() || channel.is_closed() {
+            return Ok(());
+        }
+        // While the channel is quiet, the read waits up to its timeout, which
+        // keeps this loop off the CPU. While data comes in, it must not hold
+        // up the next chunk.
+        if waiting == (size > 0) {
+            waiting = size == 0;
+            socket.set_nonblocking(!waiting)?;
+        }
+        match socket.read(&mut buf) {
+            // The device's session disconnected and closed its end.
+            Ok(0) => return Ok(()),
+            Ok(size) => {
+                channel.stdin().write_all(&buf[..size])?;
+                traffic.sent.fetch_add(size as u64, Ordering::Relaxed);
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// `write_all` for a socket that may be non-blocking, which waits for room
+/// instead of failing.
+fn write_all(socket: &mut TcpStream, mut data: &[u8]) -> Result<(), Error> {
+    while !data.is_empty() {
+        match socket.write(data) {
+            Ok(0) => return Err(Error::io(ErrorKind::WriteZero)),
+            Ok(size) => data = &data[size..],
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ares_device_lib::DeviceManager as SharedDeviceManager;
+
+    use crate::byte_string::Encoding;
+    use crate::conn_pool::{DeviceConnection, ExecuteCommand};
+    use crate::device_manager::DeviceSettings;
+    use crate::error::Error;
+    use crate::tests::common::{test_device, SshContainer, TempDir};
+
+    /// A config directory where the container is known as `jump`, and the
+    /// device `test` is the container's own sshd, reached through `jump`.
+    fn jump_dirs(port: u16) -> TempDir {
+        let dir = TempDir::new();
+        let mut jump = test_device(port);
+        jump.name = String::from("jump");
+        SharedDeviceManager::with_dirs(dir.path().to_path_buf(), dir.path().to_path_buf())
+            .add(&jump)
+            .unwrap();
+        DeviceSettings {
+            jump_host: Some(String::from("jump")),
+            ..DeviceSettings::default()
+        }
+        .save(dir.path(), "test")
+        .unwrap();
+        dir
+    }
+
+    #[test]
+    fn tunnel_exec() {
+        let sshd = SshContainer::new();
+        let dir = jump_dirs(sshd.wait());
+        let conn = DeviceConnection::new(test_device(22), Some(dir.path()), Some(dir.path()))
+            .expect("Failed to connect through jump host");
+        let output = conn
+            .execute_command("whoami", None, Encoding::String)
+            .expect("Failed to execute command");
+        assert_eq!(b"root\n", output.stdout.as_ref());
+    }
+
+    #[test]
+    fn tunnel_chained() {
+        let sshd = SshContainer::new();
+        let dir = jump_dirs(sshd.wait());
+        DeviceSettings {
+            jump_host: Some(String::from("test")),
+            ..DeviceSettings::default()
+        }
+        .save(dir.path(), "jump")
+        .unwrap();
+        let err = DeviceConnection::new(test_device(22), Some(dir.path()), Some(dir.path()))
+            .expect_err("Should have failed");
+        assert!(
+            matches!(err, Error::Message { ref message, .. } if message.contains("of its own"))
+        );
+    }
+}
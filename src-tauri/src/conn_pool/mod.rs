@@ -1,12 +1,19 @@
-use crate::device_manager::Device;
+use crate::conn_pool::capabilities::SharedCapabilities;
+use crate::device_manager::{Device, DeviceSettings};
 use crate::error::Error;
 use libssh_rs::Session;
 use r2d2::{Pool, PooledConnection};
+use serde::Serialize;
+use std::collections::HashMap;
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
 use uuid::Uuid;
 
+pub mod capabilities;
 pub mod connection;
+mod jump;
+pub mod prompt;
 pub mod pool;
 mod cmd;
 
@@ -14,17 +21,23 @@ pub struct DeviceConnection {
     id: Uuid,
     pub device: Device,
     pub user: Option<DeviceConnectionUserInfo>,
+    pub settings: DeviceSettings,
+    capabilities: SharedCapabilities,
     session: Session,
     last_ok: Mutex<bool>,
+    last_alive: Mutex<Instant>,
+    created_at: Instant,
+    live: Option<LiveConnections>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct DeviceConnectionUserInfo {
     pub uid: Id,
     pub gid: Id,
     pub groups: Vec<Id>,
 }
 
+#[derive(Clone, Serialize)]
 pub struct Id {
     pub id: u32,
     pub name: Option<String>,
@@ -35,11 +48,46 @@ pub type ManagedDeviceConnection = PooledConnection<DeviceConnectionManager>;
 pub struct DeviceConnectionPool {
     inner: Pool<DeviceConnectionManager>,
     last_error: Arc<Mutex<Option<Error>>>,
+    live: LiveConnections,
+    shared: Arc<Mutex<Weak<DeviceConnection>>>,
+    capabilities: SharedCapabilities,
 }
 
 pub struct DeviceConnectionManager {
     device: Device,
     ssh_dir: Option<PathBuf>,
+    conf_dir: Option<PathBuf>,
+    settings: DeviceSettings,
+    live: LiveConnections,
+    capabilities: SharedCapabilities,
 }
 
-pub use cmd::ExecuteCommand;
+/// The sessions a pool holds, idle or handed out. r2d2 does not let anyone look
+/// at its connections, so each one adds itself here and removes itself on drop.
+type LiveConnections = Arc<Mutex<HashMap<Uuid, LiveConnection>>>;
+
+struct LiveConnection {
+    user: Option<DeviceConnectionUserInfo>,
+    created_at: Instant,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceConnectionPoolStatus {
+    pub device: String,
+    pub connections: u32,
+    pub idle_connections: u32,
+    pub last_error: Option<Error>,
+    pub sessions: Vec<DeviceConnectionStatus>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct DeviceConnectionStatus {
+    pub id: String,
+    pub user: Option<DeviceConnectionUserInfo>,
+    /// Seconds since the session logged in.
+    pub age: f64,
+}
+
+pub use cmd::{ExecOptions, ExecuteCommand};
+pub(crate) use jump::{copy, Traffic, POLL_INTERVAL};
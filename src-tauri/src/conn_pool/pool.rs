@@ -1,38 +1,103 @@
 use std::fmt::Debug;
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::sleep;
 use std::time::Duration;
 
 use r2d2::{HandleError, ManageConnection, Pool};
 
+use crate::conn_pool::capabilities::{DeviceCapabilities, SharedCapabilities};
+use crate::conn_pool::connection::PROBE_TIMEOUT;
 use crate::conn_pool::{
-    DeviceConnection, DeviceConnectionManager, DeviceConnectionPool, ManagedDeviceConnection,
+    DeviceConnection, DeviceConnectionManager, DeviceConnectionPool, DeviceConnectionPoolStatus,
+    DeviceConnectionStatus, LiveConnection, LiveConnections, ManagedDeviceConnection,
 };
-use crate::device_manager::Device;
+use crate::device_manager::{Device, DeviceSettings};
 use crate::error::Error;
 
+/// How many times [`DeviceConnectionPool::open_shared`] tries, and the wait
+/// before the second try, which grows by as much for each one after.
+const SHARED_OPEN_ATTEMPTS: u32 = 3;
+const SHARED_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
 impl DeviceConnectionPool {
-    pub fn new(device: Device, ssh_dir: Option<PathBuf>) -> DeviceConnectionPool {
+    pub fn new(
+        device: Device,
+        ssh_dir: Option<PathBuf>,
+        conf_dir: Option<PathBuf>,
+    ) -> DeviceConnectionPool {
+        let settings = DeviceSettings::load(conf_dir.as_deref(), &device.name);
         let last_error = Arc::<Mutex<Option<Error>>>::default();
+        let live = LiveConnections::default();
+        let capabilities = SharedCapabilities::default();
         let inner = Pool::<DeviceConnectionManager>::builder()
             .min_idle(Some(0))
-            .max_size(3)
-            .idle_timeout(Some(Duration::from_secs(900)))
+            .max_size(settings.max_sessions())
+            .idle_timeout(settings.idle_timeout())
+            .connection_timeout(settings.checkout_timeout())
             .error_handler(Box::new(DeviceConnectionErrorHandler {
                 last_error: last_error.clone(),
             }))
-            .build_unchecked(DeviceConnectionManager { device, ssh_dir });
-        DeviceConnectionPool { inner, last_error }
+            .build_unchecked(DeviceConnectionManager {
+                device,
+                ssh_dir,
+                conf_dir,
+                settings,
+                live: live.clone(),
+                capabilities: capabilities.clone(),
+            });
+        DeviceConnectionPool {
+            inner,
+            last_error,
+            live,
+            shared: Arc::default(),
+            capabilities,
+        }
+    }
+
+    pub fn status(&self, device: &str) -> DeviceConnectionPoolStatus {
+        let state = self.inner.state();
+        let mut sessions: Vec<DeviceConnectionStatus> = self
+            .live
+            .lock()
+            .expect("Failed to lock DeviceConnectionPool::live")
+            .iter()
+            .map(|(id, conn)| DeviceConnectionStatus {
+                id: id.to_string(),
+                user: conn.user.clone(),
+                age: conn.created_at.elapsed().as_secs_f64(),
+            })
+            .collect();
+        sessions.sort_by(|a, b| b.age.total_cmp(&a.age));
+        DeviceConnectionPoolStatus {
+            device: device.to_string(),
+            connections: state.connections,
+            idle_connections: state.idle_connections,
+            last_error: self.last_error.lock().unwrap().clone(),
+            sessions,
+        }
+    }
+
+    /// What the device can do, once a session of this pool has probed it.
+    /// Never logs in to find out.
+    pub fn capabilities(&self) -> Option<DeviceCapabilities> {
+        self.capabilities
+            .lock()
+            .expect("Failed to lock DeviceConnectionPool::capabilities")
+            .clone()
     }
 
     pub fn get(&self) -> Result<ManagedDeviceConnection, Error> {
         match self.inner.get() {
             Ok(c) => {
+                self.last_error.lock().unwrap().take();
                 c.reset_last_ok();
                 Ok(c)
             }
+            // Cloned rather than taken, so the diagnostics still show why the
+            // pool can't connect until a checkout succeeds again.
             Err(e) => {
-                return Err(self.last_error.lock().unwrap().take().unwrap_or_else(|| {
+                return Err(self.last_error.lock().unwrap().clone().unwrap_or_else(|| {
                     Error::Message {
                         message: format!("Unknown error getting connection from pool: {e:?}"),
                         unhandled: true,
@@ -41,6 +106,87 @@ impl DeviceConnectionPool {
             }
         }
     }
+
+    /// Checks that an idle session of this pool still answers, or `None` if
+    /// no session is idle. Never logs in, so checks on a timer neither ask the
+    /// person anything nor fill the device's logs.
+    pub fn probe(&self) -> Option<Result<(), Error>> {
+        let conn = self.inner.try_get()?;
+        // Thrown away by the pool unless the probe passes.
+        conn.reset_last_ok();
+        let result = conn.probe(Some(PROBE_TIMEOUT));
+        if result.is_ok() {
+            conn.mark_last_ok();
+        }
+        Some(result)
+    }
+
+    /// A session outside the pool, for callers that hold one for as long as
+    /// they run. Those would otherwise keep slots of the pool from short
+    /// commands for hours.
+    pub fn dedicated(&self) -> Result<DeviceConnection, Error> {
+        match self.inner.manager().connect() {
+            Ok(conn) => {
+                self.last_error.lock().unwrap().take();
+                Ok(conn)
+            }
+            Err(e) => {
+                *self.last_error.lock().unwrap() = Some(e.clone());
+                Err(e)
+            }
+        }
+    }
+
+    /// A dedicated session for long-lived channels such as shells, shared by
+    /// everyone who holds it. It closes when the last holder drops it.
+    pub fn shared(&self) -> Result<Arc<DeviceConnection>, Error> {
+        // Held while a new session logs in, so shells opened together wait for
+        // one login instead of making one each.
+        let mut shared = self
+            .shared
+            .lock()
+            .expect("Failed to lock DeviceConnectionPool::shared");
+        if let Some(conn) = shared.upgrade().filter(|conn| conn.is_connected()) {
+            return Ok(conn);
+        }
+        let conn = Arc::new(self.dedicated()?);
+        *shared = Arc::downgrade(&conn);
+        Ok(conn)
+    }
+
+    /// Opens a channel on the shared session with `open`. A session that
+    /// turns out to be gone is given up, so the next attempt logs in again,
+    /// up to [`SHARED_OPEN_ATTEMPTS`] times.
+    pub fn open_shared<T, F>(&self, open: F) -> Result<(Arc<DeviceConnection>, T), Error>
+    where
+        F: Fn(&DeviceConnection) -> Result<T, Error>,
+    {
+        let mut attempt = 1;
+        loop {
+            let conn = self.shared()?;
+            match open(&conn) {
+                Ok(channel) => return Ok((conn, channel)),
+                Err(Error::Disconnected) if attempt < SHARED_OPEN_ATTEMPTS => {
+                    log::info!("{conn:?} is gone, logging in again");
+                    self.forget_shared(&conn);
+                    drop(conn);
+                    sleep(SHARED_RETRY_INTERVAL * attempt);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn forget_shared(&self, conn: &Arc<DeviceConnection>) {
+        let mut shared = self
+            .shared
+            .lock()
+            .expect("Failed to lock DeviceConnectionPool::shared");
+        if std::ptr::eq(shared.as_ptr(), Arc::as_ptr(conn)) {
+            *shared = Weak::new();
+        }
+    }
 }
 
 impl ManageConnection for DeviceConnectionManager {
@@ -48,11 +194,38 @@ impl ManageConnection for DeviceConnectionManager {
     type Error = Error;
 
     fn connect(&self) -> Result<Self::Connection, Self::Error> {
-        DeviceConnection::new(self.device.clone(), self.ssh_dir.as_deref())
+        let mut conn = DeviceConnection::new(
+            self.device.clone(),
+            self.ssh_dir.as_deref(),
+            self.conf_dir.as_deref(),
+        )?;
+        self.live
+            .lock()
+            .expect("Failed to lock DeviceConnectionPool::live")
+            .insert(
+                conn.id,
+                LiveConnection {
+                    user: conn.user.clone(),
+                    created_at: conn.created_at,
+                },
+            );
+        conn.live = Some(self.live.clone());
+        conn.capabilities = self.capabilities.clone();
+        Ok(conn)
     }
 
-    fn is_valid(&self, _: &mut Self::Connection) -> Result<(), Self::Error> {
-        Ok(())
+    /// Probes a session that sat unused for longer than the device's server
+    /// alive interval, so a dead one is replaced before anyone uses it.
+    fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        let Some(interval) = self.settings.server_alive_interval() else {
+            return Ok(());
+        };
+        if conn.idle_time() < interval {
+            return Ok(());
+        }
+        conn.probe(Some(PROBE_TIMEOUT)).inspect_err(|e| {
+            log::info!("{conn:?} failed the liveness probe: {e:?}");
+        })
     }
 
     fn has_broken(&self, conn: &mut Self::Connection) -> bool {
@@ -86,6 +259,9 @@ impl Clone for DeviceConnectionPool {
         DeviceConnectionPool {
             inner: self.inner.clone(),
             last_error: self.last_error.clone(),
+            live: self.live.clone(),
+            shared: self.shared.clone(),
+            capabilities: self.capabilities.clone(),
         }
     }
 }
@@ -0,0 +1,124 @@
+//! Keyboard-interactive login, for devices that offer neither password nor
+//! public key login, and for PAM setups that ask more than one question.
+
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use libssh_rs::{AuthStatus, Session};
+use serde::Serialize;
+
+use crate::device_manager::Device;
+use crate::error::Error;
+
+/// Shows the questions of a login to the person and waits for the answers.
+pub trait AuthPrompter {
+    /// The answers in the order of the prompts, or `None` if the person
+    /// cancelled or didn't answer within `timeout`.
+    fn prompt(&self, device: &str, request: &AuthPrompt, timeout: Duration) -> Option<Vec<String>>;
+}
+
+/// Connections are made deep inside the pool, far from any window, so the
+/// prompter is set once for the whole app.
+static PROMPTER: OnceLock<Box<dyn AuthPrompter + Send + Sync>> = OnceLock::new();
+
+pub fn set_prompter(prompter: Box<dyn AuthPrompter + Send + Sync>) {
+    if PROMPTER.set(prompter).is_err() {
+        log::warn!("The login prompter was already set");
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct AuthPrompt {
+    pub name: String,
+    pub instruction: String,
+    pub prompts: Vec<AuthPromptItem>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct AuthPromptItem {
+    pub text: String,
+    /// Whether the answer may be shown while it is typed.
+    pub echo: bool,
+}
+
+impl AuthPrompt {
+    /// An expired password shows up as PAM asking for a new one. Answering
+    /// that here would change the password behind the person's back.
+    fn wants_password_change(&self) -> bool {
+        let texts = [&self.name, &self.instruction]
+            .into_iter()
+            .chain(self.prompts.iter().map(|p| &p.text));
+        texts.map(|t| t.to_lowercase()).any(|t| {
+            t.contains("new password")
+                || t.contains("password has expired")
+                || t.contains("change your password")
+        })
+    }
+
+    /// The one question a password login would answer.
+    fn is_password(&self) -> bool {
+        match &self.prompts[..] {
+            [item] => !item.echo && item.text.to_lowercase().contains("password"),
+            _ => false,
+        }
+    }
+}
+
+/// Runs the question rounds until the device lets the person in or refuses.
+/// A plain password question is answered with the device's password, the
+/// first time only, so a wrong one still reaches the person. The person has
+/// until `deadline` to answer all of them.
+pub(super) fn login(session: &Session, device: &Device, deadline: Instant) -> Result<(), Error> {
+    let username = Some(device.username.as_str());
+    let mut password = device.password.clone();
+    let mut status = session.userauth_keyboard_interactive(username, None)?;
+    loop {
+        match status {
+            AuthStatus::Success => return Ok(()),
+            AuthStatus::Info => {}
+            _ => {
+                return Err(Error::Authorization {
+                    message: format!("{} refused the keyboard-interactive login", device.name),
+                });
+            }
+        }
+        let info = session.userauth_keyboard_interactive_info()?;
+        let request = AuthPrompt {
+            name: info.name,
+            instruction: info.instruction,
+            prompts: info
+                .prompts
+                .into_iter()
+                .map(|p| AuthPromptItem {
+                    text: p.prompt,
+                    echo: p.echo,
+                })
+                .collect(),
+        };
+        if request.wants_password_change() {
+            return Err(Error::PasswordChangeRequired {
+                message: format!("{}\n{}", request.instruction, request.name)
+                    .trim()
+                    .to_string(),
+            });
+        }
+        let answers = if request.prompts.is_empty() {
+            Vec::new()
+        } else if let Some(password) = password.take_if(|_| request.is_password()) {
+            vec![password]
+        } else {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                return Err(Error::Timeout);
+            }
+            PROMPTER
+                .get()
+                .and_then(|prompter| prompter.prompt(&device.name, &request, timeout))
+                .ok_or_else(|| Error::Authorization {
+                    message: format!("Login to {} was cancelled", device.name),
+                })?
+        };
+        session.userauth_keyboard_interactive_set_answers(&answers)?;
+        status = session.userauth_keyboard_interactive(username, None)?;
+    }
+}
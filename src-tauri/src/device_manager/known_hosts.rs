@@ -0,0 +1,150 @@
+//! Host key fingerprints of the devices this app has connected to.
+//!
+//! The first connection to a device records the key it presents, and every
+//! later connection has to present the same one. The webOS SDK has no place
+//! for host keys, so they live in a [`DeviceStore`] of their own, keyed by
+//! device name with the address the key was seen at alongside.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::device_manager::store::DeviceStore;
+use crate::device_manager::Device;
+use crate::error::Error;
+
+const FILE_NAME: &str = "devman-known-hosts.json";
+
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+struct KnownHost {
+    host: String,
+    port: u16,
+    fingerprint: String,
+    /// The key the device presented when it last failed verification, which
+    /// is the only key the person can accept for it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pending: Option<String>,
+}
+
+pub struct KnownHosts {
+    store: DeviceStore<KnownHost>,
+}
+
+impl KnownHosts {
+    pub fn in_dir(conf_dir: &Path) -> Self {
+        KnownHosts {
+            store: DeviceStore::in_dir(conf_dir, FILE_NAME),
+        }
+    }
+
+    /// Trusts `fingerprint` if nothing is recorded for the device at its
+    /// current address yet, and otherwise fails with
+    /// [`Error::HostKeyMismatch`] unless it is the recorded one.
+    pub fn verify(&self, device: &Device, fingerprint: &str) -> Result<(), Error> {
+        let name = &device.name;
+        self.store.update(|entries| {
+            let verified = match entries.get_mut(name) {
+                Some(known) if known.host == device.host && known.port == device.port => {
+                    if known.fingerprint == fingerprint {
+                        known.pending = None;
+                        Ok(())
+                    } else {
+                        known.pending = Some(fingerprint.to_string());
+                        Err(Error::HostKeyMismatch {
+                            expected: known.fingerprint.clone(),
+                            actual: fingerprint.to_string(),
+                        })
+                    }
+                }
+                // A device moved to another address is another host as far as
+                // its key goes.
+                _ => {
+                    log::info!("Trusting host key {fingerprint} of {name} on first use");
+                    entries.insert(
+                        name.clone(),
+                        KnownHost {
+                            host: device.host.clone(),
+                            port: device.port,
+                            fingerprint: fingerprint.to_string(),
+                            pending: None,
+                        },
+                    );
+                    Ok(())
+                }
+            };
+            // The mismatch goes back only after the pending key is written.
+            Ok(verified)
+        })?
+    }
+
+    /// Records the key the device presented when it last failed
+    /// verification, once the person has accepted it. Fails with
+    /// [`Error::NotFound`] if no such key is waiting.
+    pub fn accept(&self, name: &str) -> Result<(), Error> {
+        self.store.update(|entries| {
+            let Some(known) = entries.get_mut(name) else {
+                return Err(Error::NotFound);
+            };
+            let Some(fingerprint) = known.pending.take() else {
+                return Err(Error::NotFound);
+            };
+            log::info!("Accepting new host key {fingerprint} of {name}");
+            known.fingerprint = fingerprint;
+            Ok(())
+        })
+    }
+
+    /// Drops the key of a removed device, so a new device that takes its name
+    /// is trusted on first use again.
+    pub fn forget(&self, name: &str) -> Result<(), Error> {
+        self.store.remove(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::device_manager::known_hosts::KnownHosts;
+    use crate::error::Error;
+    use crate::tests::common::{test_device, TempDir};
+
+    #[test]
+    fn trust_on_first_use() {
+        let dir = TempDir::new();
+        let hosts = KnownHosts::in_dir(dir.path());
+        let device = test_device(22);
+        hosts
+            .verify(&device, "aa:bb")
+            .expect("First key should be trusted");
+        hosts
+            .verify(&device, "aa:bb")
+            .expect("Same key should pass");
+        assert_eq!(
+            Error::HostKeyMismatch {
+                expected: String::from("aa:bb"),
+                actual: String::from("cc:dd"),
+            },
+            hosts.verify(&device, "cc:dd").unwrap_err()
+        );
+        hosts.accept("test").unwrap();
+        assert_eq!(Error::NotFound, hosts.accept("test").unwrap_err());
+        hosts
+            .verify(&device, "cc:dd")
+            .expect("Accepted key should pass");
+        hosts.forget("test").unwrap();
+        hosts
+            .verify(&device, "ee:ff")
+            .expect("Forgotten device should be trusted again");
+    }
+
+    #[test]
+    fn moved_device_trusted_again() {
+        let dir = TempDir::new();
+        let hosts = KnownHosts::in_dir(dir.path());
+        hosts.verify(&test_device(22), "aa:bb").unwrap();
+        hosts
+            .verify(&test_device(9922), "cc:dd")
+            .expect("Key at a new address should be trusted");
+        assert!(hosts.verify(&test_device(9922), "aa:bb").is_err());
+    }
+}
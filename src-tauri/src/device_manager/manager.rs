@@ -1,7 +1,8 @@
 
+use crate::device_manager::known_hosts::KnownHosts;
 use crate::device_manager::privkey::PrivateKeyExt;
 use crate::device_manager::{
-    Device, DeviceCheckConnection, DeviceManager, PrivateKey, PrivateKeyInfo,
+    Device, DeviceCheckConnection, DeviceManager, DeviceSettings, PrivateKey, PrivateKeyInfo,
 };
 use crate::error::Error;
 use ares_connection_lib::setup::{fetch_key, NOVACOM_KEY_PORT};
@@ -81,9 +82,30 @@ impl DeviceManager {
     /// asks the person to confirm first, so there is nothing left to protect
     /// them from.
     pub async fn remove(&self, name: &str, remove_key: bool) -> Result<(), Error> {
-        let name = name.to_string();
-        self.with_shared(move |shared| shared.remove(&name, remove_key, true))
-            .await
+        let device_name = name.to_string();
+        self.with_shared(move |shared| shared.remove(&device_name, remove_key, true))
+            .await?;
+        let conf_dir = self.conf_dir.ensure()?;
+        DeviceSettings::remove(conf_dir, name)?;
+        KnownHosts::in_dir(conf_dir).forget(name)
+    }
+
+    pub async fn settings(&self, name: &str) -> Result<DeviceSettings, Error> {
+        Ok(DeviceSettings::load(Some(self.conf_dir.ensure()?), name))
+    }
+
+    pub async fn save_settings(&self, name: &str, settings: &DeviceSettings) -> Result<(), Error> {
+        if settings.jump_host.as_deref() == Some(name) {
+            return Err(Error::new("A device can't be its own jump host"));
+        }
+        log::info!("Save settings of {name}: {settings:?}");
+        settings.save(self.conf_dir.ensure()?, name)
+    }
+
+    /// Trusts the key a device now presents, after a connection failed with
+    /// [`Error::HostKeyMismatch`] and the person accepted the new key.
+    pub async fn accept_host_key(&self, name: &str) -> Result<(), Error> {
+        KnownHosts::in_dir(self.conf_dir.ensure()?).accept(name)
     }
 
     pub async fn novacom_getkey(&self, address: &str, passphrase: &str) -> Result<String, Error> {
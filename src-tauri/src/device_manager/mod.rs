@@ -4,8 +4,11 @@ use serde::{Deserialize, Serialize};
 
 use crate::app_dirs::DirSlot;
 
+pub mod known_hosts;
 mod manager;
 pub mod privkey;
+mod settings;
+mod store;
 
 // Device, its key and its file transfer mode are shared with the ares-cli-rs
 // tools. Reading and writing the device list is shared too, through
@@ -39,3 +42,40 @@ pub struct DeviceCheckConnection {
     pub ssh_9922: bool,
     pub key_server: bool,
 }
+
+/// Connection tuning for one device, kept next to the device list because the
+/// webOS SDK has no place for it.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceSettings {
+    /// How many sessions the pool may hold at once.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_sessions: Option<u32>,
+    /// Seconds an unused pooled session stays open. `0` keeps it open.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_timeout: Option<u64>,
+    /// Seconds to wait for the device to answer, when connecting and on every
+    /// blocking call after that.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<u64>,
+    /// Seconds a session may sit unused before it is probed. `0` turns the
+    /// probe off.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_alive_interval: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<bool>,
+    /// Name of another device to connect through, for devices only reachable
+    /// from a jump box.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jump_host: Option<String>,
+    /// Try the identities of the running SSH agent before the device's own
+    /// key and password.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssh_agent: Option<bool>,
+    /// Socket of the agent to use instead of the one in `SSH_AUTH_SOCK`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssh_agent_socket: Option<String>,
+    /// Have the device monitor log in and probe, not just check the port.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monitor_ssh: Option<bool>,
+}
@@ -0,0 +1,97 @@
+use std::path::Path;
+use std::time::Duration;
+
+use crate::conn_pool::connection::SESSION_TIMEOUT;
+use crate::device_manager::store::DeviceStore;
+use crate::device_manager::DeviceSettings;
+use crate::error::Error;
+
+const FILE_NAME: &str = "devman-device-settings.json";
+
+const DEFAULT_MAX_SESSIONS: u32 = 3;
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(900);
+
+/// A TV that went to sleep keeps its TCP connection looking open for minutes,
+/// so a session idle for longer than this is checked before it is used.
+const DEFAULT_SERVER_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+impl DeviceSettings {
+    /// The settings saved for a device. Missing or unreadable settings fall back
+    /// to the defaults, because a device must stay reachable either way.
+    pub fn load(conf_dir: Option<&Path>, name: &str) -> DeviceSettings {
+        let Some(conf_dir) = conf_dir else {
+            return DeviceSettings::default();
+        };
+        match Self::store(conf_dir).get(name) {
+            Ok(settings) => settings.unwrap_or_default(),
+            Err(e) => {
+                log::warn!("Failed to read settings of {name}: {e:?}");
+                DeviceSettings::default()
+            }
+        }
+    }
+
+    pub fn save(&self, conf_dir: &Path, name: &str) -> Result<(), Error> {
+        if *self == DeviceSettings::default() {
+            return Self::remove(conf_dir, name);
+        }
+        Self::store(conf_dir).set(name, self.clone())
+    }
+
+    pub fn remove(conf_dir: &Path, name: &str) -> Result<(), Error> {
+        Self::store(conf_dir).remove(name)
+    }
+
+    pub fn max_sessions(&self) -> u32 {
+        self.max_sessions.unwrap_or(DEFAULT_MAX_SESSIONS).max(1)
+    }
+
+    /// How long an unused pooled session stays open, or `None` if it stays
+    /// open until it breaks.
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        match self.idle_timeout {
+            Some(0) => None,
+            Some(secs) => Some(Duration::from_secs(secs)),
+            None => Some(DEFAULT_IDLE_TIMEOUT),
+        }
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        match self.connect_timeout {
+            Some(0) | None => SESSION_TIMEOUT,
+            Some(secs) => Duration::from_secs(secs),
+        }
+    }
+
+    /// How long a caller waits for a pooled session, with room for the retries
+    /// a dropped connection gets.
+    pub fn checkout_timeout(&self) -> Duration {
+        self.connect_timeout() * 3
+    }
+
+    /// How long a session may sit unused before it is probed, or `None` if it
+    /// never is.
+    pub fn server_alive_interval(&self) -> Option<Duration> {
+        match self.server_alive_interval {
+            Some(0) => None,
+            Some(secs) => Some(Duration::from_secs(secs)),
+            None => Some(DEFAULT_SERVER_ALIVE_INTERVAL),
+        }
+    }
+
+    pub fn compression(&self) -> bool {
+        self.compression.unwrap_or(false)
+    }
+
+    pub fn ssh_agent(&self) -> bool {
+        self.ssh_agent.unwrap_or(false)
+    }
+
+    pub fn monitor_ssh(&self) -> bool {
+        self.monitor_ssh.unwrap_or(false)
+    }
+
+    fn store(conf_dir: &Path) -> DeviceStore<DeviceSettings> {
+        DeviceStore::in_dir(conf_dir, FILE_NAME)
+    }
+}
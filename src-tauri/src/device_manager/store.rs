@@ -0,0 +1,80 @@
+//! Per-device records this app keeps next to the device list.
+//!
+//! The device list itself belongs to the webOS SDK and the ares-cli-rs tools,
+//! so anything only this app needs goes into a JSON file of its own, keyed by
+//! device name.
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::Error;
+
+/// Pools for different devices connect in parallel, and each may update a
+/// record while another is being read.
+static FILE_LOCK: Mutex<()> = Mutex::new(());
+
+pub struct DeviceStore<T> {
+    path: PathBuf,
+    _record: PhantomData<T>,
+}
+
+impl<T> DeviceStore<T>
+where
+    T: Serialize + DeserializeOwned + PartialEq + Clone,
+{
+    pub fn in_dir(conf_dir: &Path, file_name: &str) -> Self {
+        DeviceStore {
+            path: conf_dir.join(file_name),
+            _record: PhantomData,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<T>, Error> {
+        let _guard = FILE_LOCK.lock().expect("Failed to lock device store");
+        Ok(self.read()?.remove(name))
+    }
+
+    /// Reads every record, lets `f` change them, and writes them back if
+    /// anything changed.
+    pub fn update<R, F>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut BTreeMap<String, T>) -> Result<R, Error>,
+    {
+        let _guard = FILE_LOCK.lock().expect("Failed to lock device store");
+        let old = self.read()?;
+        let mut entries = old.clone();
+        let ret = f(&mut entries)?;
+        if entries != old {
+            std::fs::write(&self.path, serde_json::to_vec_pretty(&entries)?)?;
+        }
+        Ok(ret)
+    }
+
+    pub fn set(&self, name: &str, record: T) -> Result<(), Error> {
+        self.update(|entries| {
+            entries.insert(name.to_string(), record);
+            Ok(())
+        })
+    }
+
+    pub fn remove(&self, name: &str) -> Result<(), Error> {
+        self.update(|entries| {
+            entries.remove(name);
+            Ok(())
+        })
+    }
+
+    fn read(&self) -> Result<BTreeMap<String, T>, Error> {
+        match std::fs::read(&self.path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
@@ -0,0 +1,32 @@
+//! Keeps track of which devices answer, so the device list can show it
+//! without anyone running a command.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tauri::async_runtime::JoinHandle;
+
+mod monitor;
+mod worker;
+
+#[derive(Default)]
+pub struct DeviceMonitor {
+    statuses: Mutex<HashMap<String, DeviceStatus>>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceStatus {
+    pub name: String,
+    pub online: bool,
+    /// Milliseconds the SSH port took to accept a connection, never known for
+    /// a device behind a jump host.
+    pub latency: Option<f64>,
+    /// Unix time in milliseconds when the device last answered.
+    pub last_seen: Option<u64>,
+    /// Whether the SSH probe passed, for devices that ask for one and have an
+    /// idle session to probe.
+    pub ssh: Option<bool>,
+}
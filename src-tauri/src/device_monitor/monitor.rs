@@ -0,0 +1,90 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use port_check::is_port_reachable_with_timeout;
+
+use crate::device_manager::{Device, DeviceSettings};
+use crate::device_monitor::{DeviceMonitor, DeviceStatus};
+use crate::session_manager::SessionManager;
+
+/// How long the port check waits. A device on the local network answers in a
+/// few milliseconds, so anything slower counts as offline.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+impl DeviceMonitor {
+    /// The last known status of every device, sorted by name.
+    pub fn list(&self) -> Vec<DeviceStatus> {
+        let mut list: Vec<DeviceStatus> = self
+            .statuses
+            .lock()
+            .expect("Failed to lock DeviceMonitor::statuses")
+            .values()
+            .cloned()
+            .collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        list
+    }
+
+    /// Forgets devices that are no longer in the list.
+    pub fn retain(&self, devices: &[Device]) {
+        self.statuses
+            .lock()
+            .expect("Failed to lock DeviceMonitor::statuses")
+            .retain(|name, _| devices.iter().any(|d| &d.name == name));
+    }
+
+    /// Checks whether the SSH port of `device` accepts connections, and with
+    /// the device's `monitorSsh` setting, whether an idle session of its pool
+    /// still answers. A device behind a jump host has no port this machine
+    /// can reach, so only its sessions tell, and without an idle one it keeps
+    /// the status it had. Nothing here logs in. Blocks for up to a few
+    /// seconds.
+    pub fn check(
+        &self,
+        device: &Device,
+        settings: &DeviceSettings,
+        sessions: &SessionManager,
+    ) -> DeviceStatus {
+        let jumped = settings.jump_host.is_some();
+        let started_at = Instant::now();
+        let reachable = jumped
+            || is_port_reachable_with_timeout(
+                format!("{}:{}", device.host, device.port),
+                CHECK_TIMEOUT,
+            );
+        let latency = (reachable && !jumped).then(|| started_at.elapsed().as_secs_f64() * 1000.0);
+        let ssh = (reachable && (jumped || settings.monitor_ssh()))
+            .then(|| sessions.cached_pool(&device.name)?.probe())
+            .flatten()
+            .map(|result| {
+                result
+                    .inspect_err(|e| log::debug!("SSH probe of {} failed: {e:?}", device.name))
+                    .is_ok()
+            });
+        let mut statuses = self
+            .statuses
+            .lock()
+            .expect("Failed to lock DeviceMonitor::statuses");
+        let online = match ssh {
+            Some(ssh) => reachable && ssh,
+            None if jumped => statuses.get(&device.name).is_some_and(|s| s.online),
+            None => reachable,
+        };
+        let last_seen = if online {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|d| d.as_millis() as u64)
+        } else {
+            statuses.get(&device.name).and_then(|s| s.last_seen)
+        };
+        let status = DeviceStatus {
+            name: device.name.clone(),
+            online,
+            latency,
+            last_seen,
+            ssh,
+        };
+        statuses.insert(device.name.clone(), status.clone());
+        status
+    }
+}
@@ -0,0 +1,65 @@
+use std::sync::MutexGuard;
+use std::time::Duration;
+
+use tauri::async_runtime::{self, JoinHandle};
+use tauri::{AppHandle, Emitter, Manager, Runtime};
+
+use crate::device_manager::DeviceManager;
+use crate::device_monitor::DeviceMonitor;
+use crate::session_manager::SessionManager;
+
+/// How often every device is checked.
+const MONITOR_INTERVAL: Duration = Duration::from_secs(15);
+
+impl DeviceMonitor {
+    /// Checks all devices at once, every [`MONITOR_INTERVAL`], and announces
+    /// each result on `device-status`, until [`stop`](Self::stop) is called.
+    pub fn start<R: Runtime>(&self, app: AppHandle<R>) {
+        if let Some(old) = self.task().replace(async_runtime::spawn(run(app))) {
+            old.abort();
+        }
+    }
+
+    pub fn stop(&self) {
+        if let Some(task) = self.task().take() {
+            task.abort();
+        }
+    }
+
+    fn task(&self) -> MutexGuard<'_, Option<JoinHandle<()>>> {
+        self.task
+            .lock()
+            .expect("Failed to lock DeviceMonitor::task")
+    }
+}
+
+async fn run<R: Runtime>(app: AppHandle<R>) {
+    loop {
+        tokio::time::sleep(MONITOR_INTERVAL).await;
+        let manager = app.state::<DeviceManager>();
+        // Fails until the app directories are known.
+        let devices = match manager.list().await {
+            Ok(devices) => devices,
+            Err(e) => {
+                log::debug!("Device monitor skipped a round: {e:?}");
+                continue;
+            }
+        };
+        app.state::<DeviceMonitor>().retain(&devices);
+        let mut checks = Vec::with_capacity(devices.len());
+        for device in devices {
+            let settings = manager.settings(&device.name).await.unwrap_or_default();
+            let app = app.clone();
+            // The check blocks on the port and the probe.
+            checks.push(async_runtime::spawn_blocking(move || {
+                let monitor = app.state::<DeviceMonitor>();
+                let status = monitor.check(&device, &settings, &app.state::<SessionManager>());
+                app.emit("device-status", status).unwrap_or(());
+            }));
+        }
+        // The next round starts only after the slowest device of this one.
+        for check in checks {
+            check.await.unwrap_or(());
+        }
+    }
+}
@@ -26,20 +26,36 @@ pub enum Error {
         message: String,
         command: String,
         exit_code: i32,
+        /// Set when a signal ended the command, in which case `exit_code` is -1.
+        signal: Option<String>,
         stderr: Vec<u8>,
         unhandled: bool,
     },
+    HostKeyMismatch {
+        expected: String,
+        actual: String,
+    },
     IO {
         #[serde(serialize_with = "as_debug_string")]
         code: ErrorKind,
         message: String,
         unhandled: bool,
     },
+    /// A Luna call answered with `returnValue: false`.
+    Luna {
+        error_code: Option<i64>,
+        error_text: Option<String>,
+    },
     Message {
         message: String,
         unhandled: bool,
     },
     PassphraseRequired,
+    /// The password expired, and the device wants a new one before it lets
+    /// anyone in.
+    PasswordChangeRequired {
+        message: String,
+    },
     NotFound,
     Timeout,
     Unsupported,
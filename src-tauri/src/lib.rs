@@ -6,7 +6,9 @@ use std::path::PathBuf;
 
 use crate::app_dirs::GetAppSshKeyDir;
 use crate::device_manager::DeviceManager;
+use crate::device_monitor::DeviceMonitor;
 use crate::error::Error;
+use crate::port_forward::PortForwardManager;
 use crate::session_manager::SessionManager;
 use crate::shell_manager::ShellManager;
 use crate::spawn_manager::SpawnManager;
@@ -16,11 +18,16 @@ use tauri::{AppHandle, Builder, Manager, RunEvent, Runtime};
 
 mod app_dirs;
 mod byte_string;
+mod command_line;
 mod conn_pool;
 mod device_manager;
+mod device_monitor;
 mod error;
 mod event_channel;
+mod luna;
+mod output_frames;
 mod plugins;
+mod port_forward;
 mod remote_files;
 mod session_manager;
 mod shell_manager;
@@ -46,10 +53,14 @@ pub fn run() {
         .plugin(plugins::file::plugin("remote-file"))
         .plugin(plugins::devmode::plugin("dev-mode"))
         .plugin(plugins::local_file::plugin("local-file"))
+        .plugin(plugins::forward::plugin("port-forward"))
+        .plugin(plugins::luna::plugin("luna"))
         .manage(DeviceManager::default())
+        .manage(DeviceMonitor::default())
         .manage(SessionManager::default())
         .manage(SpawnManager::default())
         .manage(ShellManager::default())
+        .manage(PortForwardManager::default())
         .register_asynchronous_uri_scheme_protocol(
             plugins::file::URI_SCHEME,
             plugins::file::protocol,
@@ -66,11 +77,11 @@ pub fn run() {
                 RunEvent::Ready => {
                     if let Some(dir) = app_dirs::ssh_dir(app) {
                         app.state::<DeviceManager>().ssh_dir.set(dir.clone());
-                        app.state::<SessionManager>().ssh_dir.set(dir.clone());
-                        app.state::<ShellManager>().ssh_dir.set(dir);
+                        app.state::<SessionManager>().ssh_dir.set(dir);
                     }
                     if let Some(dir) = app_dirs::conf_dir(app) {
-                        app.state::<DeviceManager>().conf_dir.set(dir);
+                        app.state::<DeviceManager>().conf_dir.set(dir.clone());
+                        app.state::<SessionManager>().conf_dir.set(dir);
                     }
                 }
                 _ => {}
@@ -0,0 +1,193 @@
+//! Calls to services on the webOS Luna bus, made with `luna-send` on the
+//! device. `luna-send-pub` reaches the public bus, which is all that third
+//! party apps can see, and `luna-send` the private one.
+
+use serde_json::Value;
+
+use crate::byte_string::Encoding;
+use crate::command_line::quote;
+use crate::conn_pool::{DeviceConnection, ExecuteCommand};
+use crate::error::Error;
+
+/// The exit status of a shell that found no such command.
+const COMMAND_NOT_FOUND: i32 = 127;
+
+/// The command line for `luna-send`. A subscription keeps printing responses
+/// until it is interrupted, otherwise it stops after the first one.
+pub(crate) fn command(uri: &str, payload: &Value, public: bool, subscribe: bool) -> String {
+    let program = if public { "luna-send-pub" } else { "luna-send" };
+    let count = if subscribe { "-i" } else { "-n 1" };
+    format!(
+        "{program} {count} {} {}",
+        quote(uri),
+        quote(&payload.to_string())
+    )
+}
+
+/// Calls `uri` and waits for its response.
+pub(crate) fn call(
+    session: &DeviceConnection,
+    uri: &str,
+    payload: &Value,
+    public: bool,
+) -> Result<Value, Error> {
+    let command = command(uri, payload, public, false);
+    let output = session
+        .execute_command(&command, None, Encoding::Binary)
+        .map_err(map_exit)?;
+    check(parse(output.stdout.as_ref())?)
+}
+
+/// A device without `luna-send` is no webOS device.
+pub(crate) fn map_exit(e: Error) -> Error {
+    match e {
+        Error::ExitStatus { exit_code, .. } if exit_code == COMMAND_NOT_FOUND => Error::Unsupported,
+        e => e,
+    }
+}
+
+fn parse(line: &[u8]) -> Result<Value, Error> {
+    serde_json::from_slice::<Value>(line.trim_ascii()).map_err(|e| {
+        let line = String::from_utf8_lossy(line);
+        log::warn!("Bad Luna response {line:?}: {e:?}");
+        Error::new(format!("Bad response {line}"))
+    })
+}
+
+/// Fails on a response that says so with `returnValue: false`.
+pub(crate) fn check(response: Value) -> Result<Value, Error> {
+    if response.get("returnValue").and_then(Value::as_bool) != Some(false) {
+        return Ok(response);
+    }
+    Err(Error::Luna {
+        error_code: response.get("errorCode").and_then(Value::as_i64),
+        error_text: response
+            .get("errorText")
+            .and_then(Value::as_str)
+            .map(String::from),
+    })
+}
+
+/// Splits the output of a subscription into responses, one per line.
+#[derive(Default)]
+pub(crate) struct Responses {
+    partial: Vec<u8>,
+    /// Whether the first response came, which says if the subscription took.
+    started: bool,
+}
+
+impl Responses {
+    /// The responses completed by `data`. A line that isn't JSON is skipped.
+    /// A negative first response fails, since the service refused the
+    /// subscription and sends nothing more.
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<Value>, Error> {
+        self.partial.extend_from_slice(data);
+        let mut responses = Vec::new();
+        let mut start = 0;
+        while let Some(end) = self.partial[start..].iter().position(|b| *b == b'\n') {
+            let line = &self.partial[start..start + end];
+            start += end + 1;
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            let Ok(response) = parse(line) else {
+                continue;
+            };
+            if self.started {
+                responses.push(response);
+            } else {
+                self.started = true;
+                responses.push(check(response)?);
+            }
+        }
+        self.partial.drain(..start);
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::error::Error;
+    use crate::luna::{check, command, map_exit, Responses, COMMAND_NOT_FOUND};
+
+    #[test]
+    fn command_quotes_payload() {
+        assert_eq!(
+            r#"luna-send -n 1 'luna://com.webos.service.tv.systemproperty/getSystemInfo' '{"keys":["it'\''s"]}'"#,
+            command(
+                "luna://com.webos.service.tv.systemproperty/getSystemInfo",
+                &json!({"keys": ["it's"]}),
+                false,
+                false
+            )
+        );
+    }
+
+    #[test]
+    fn check_negative_response() {
+        let response =
+            json!({"returnValue": false, "errorCode": -1, "errorText": "Unknown method"});
+        assert_eq!(
+            Err(Error::Luna {
+                error_code: Some(-1),
+                error_text: Some(String::from("Unknown method")),
+            }),
+            check(response)
+        );
+        assert!(check(json!({"returnValue": true})).is_ok());
+    }
+
+    #[test]
+    fn responses_split_lines() {
+        let mut responses = Responses::default();
+        assert!(responses
+            .push(br#"{"returnValue":true,"#)
+            .unwrap()
+            .is_empty());
+        let values = responses
+            .push(b"\"subscribed\":true}\n{\"progress\":1}\n{")
+            .unwrap();
+        assert_eq!(
+            vec![
+                json!({"returnValue": true, "subscribed": true}),
+                json!({"progress": 1})
+            ],
+            values
+        );
+    }
+
+    #[test]
+    fn responses_negative_first() {
+        let mut responses = Responses::default();
+        let refused = br#"{"returnValue":false,"errorCode":-1,"errorText":"Unknown method"}"#;
+        assert_eq!(
+            Err(Error::Luna {
+                error_code: Some(-1),
+                error_text: Some(String::from("Unknown method")),
+            }),
+            responses.push(&[&refused[..], b"\n"].concat())
+        );
+        // Only the first one decides, later ones are data.
+        let mut responses = Responses::default();
+        let values = responses
+            .push(&[&br#"{"returnValue":true}"#[..], b"\n", refused, b"\n"].concat())
+            .unwrap();
+        assert_eq!(2, values.len());
+    }
+
+    #[test]
+    fn map_exit_not_found() {
+        let exit = |exit_code| Error::ExitStatus {
+            message: String::new(),
+            command: String::from("luna-send"),
+            exit_code,
+            signal: None,
+            stderr: Vec::new(),
+            unhandled: true,
+        };
+        assert_eq!(Error::Unsupported, map_exit(exit(COMMAND_NOT_FOUND)));
+        assert_eq!(exit(1), map_exit(exit(1)));
+    }
+}
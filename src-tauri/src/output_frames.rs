@@ -0,0 +1,171 @@
+//! Output of commands and shells on its way to the frontend. Reads are
+//! gathered into frames on a short time and size budget, so a chatty command
+//! costs an IPC message per frame rather than per read. Frames the frontend
+//! hasn't acknowledged count against a small window, and reading stops while
+//! it is full, so a slow frontend holds the command back through the SSH
+//! window instead of piling up output in memory. Output that is also going
+//! to a file is dropped instead, so the file gets all of it.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// A frame is sent once it holds this much.
+const FRAME_SIZE: usize = 64 * 1024;
+
+/// A frame is sent once its oldest byte waited this long, about a display
+/// refresh.
+const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+
+/// How many frames may wait for the frontend before reading stops.
+const MAX_IN_FLIGHT: u64 = 8;
+
+/// Counts of frames sent and acknowledged by the frontend, kept across the
+/// workers of a shell that reconnects.
+#[derive(Default)]
+pub struct OutputFlow {
+    sent: AtomicU64,
+    acked: AtomicU64,
+}
+
+pub(crate) struct OutputFrames<'a> {
+    flow: &'a OutputFlow,
+    /// Whether to drop output the frontend has no room for, rather than stop
+    /// reading.
+    lossy: bool,
+    /// Output waiting to be sent on each fd, and when the oldest of it came.
+    pending: [(Vec<u8>, Option<Instant>); 2],
+}
+
+pub(crate) struct Frame {
+    pub fd: u32,
+    pub data: Vec<u8>,
+}
+
+impl OutputFlow {
+    /// Records that the frontend handled `frames` frames since the start.
+    pub fn ack(&self, frames: u64) {
+        self.acked.fetch_max(frames, Ordering::Relaxed);
+    }
+
+    /// Starts counting again, for a new frontend that never saw the frames
+    /// sent so far.
+    pub fn reset(&self) {
+        self.sent.store(0, Ordering::Relaxed);
+        self.acked.store(0, Ordering::Relaxed);
+    }
+
+    fn has_room(&self) -> bool {
+        let sent = self.sent.load(Ordering::Relaxed);
+        sent.saturating_sub(self.acked.load(Ordering::Relaxed)) < MAX_IN_FLIGHT
+    }
+}
+
+impl<'a> OutputFrames<'a> {
+    pub fn new(flow: &'a OutputFlow, lossy: bool) -> Self {
+        OutputFrames {
+            flow,
+            lossy,
+            pending: Default::default(),
+        }
+    }
+
+    /// Whether to read more of `fd`. A full frame waits for the frontend to
+    /// catch up, and nothing more is read until it is sent.
+    pub fn has_room(&self, fd: u32) -> bool {
+        self.lossy || self.pending[fd as usize].0.len() < FRAME_SIZE
+    }
+
+    pub fn push(&mut self, fd: u32, data: &[u8]) {
+        let (pending, since) = &mut self.pending[fd as usize];
+        if self.lossy && pending.len() >= FRAME_SIZE && !self.flow.has_room() {
+            log::debug!(
+                "Dropped {} bytes of output the frontend had no room for",
+                pending.len()
+            );
+            pending.clear();
+            since.take();
+        }
+        since.get_or_insert_with(Instant::now);
+        pending.extend_from_slice(data);
+    }
+
+    /// The frames due, if the frontend has room for them. With `flush`, all
+    /// that is pending, for when the output ends.
+    pub fn take(&mut self, flush: bool) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        for (fd, (pending, since)) in self.pending.iter_mut().enumerate() {
+            let due = since.is_some_and(|since| {
+                pending.len() >= FRAME_SIZE || since.elapsed() >= FRAME_INTERVAL
+            });
+            if (flush && !pending.is_empty()) || (due && self.flow.has_room()) {
+                since.take();
+                self.flow.sent.fetch_add(1, Ordering::Relaxed);
+                frames.push(Frame {
+                    fd: fd as u32,
+                    data: std::mem::take(pending),
+                });
+            }
+        }
+        frames
+    }
+}
+
+/// What goes over the IPC channel: the fd in the first byte, then the data.
+pub(crate) fn encode_frame(fd: u32, data: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(data.len() + 1);
+    frame.push(fd as u8);
+    frame.extend_from_slice(data);
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use crate::output_frames::{OutputFlow, OutputFrames, FRAME_INTERVAL, FRAME_SIZE};
+
+    #[test]
+    fn coalesces_reads() {
+        let flow = OutputFlow::default();
+        let mut frames = OutputFrames::new(&flow, false);
+        frames.push(0, b"hello ");
+        frames.push(0, b"world");
+        assert!(frames.take(false).is_empty());
+        sleep(FRAME_INTERVAL);
+        let taken = frames.take(false);
+        assert_eq!(1, taken.len());
+        assert_eq!(b"hello world", taken[0].data.as_slice());
+    }
+
+    #[test]
+    fn stops_when_behind() {
+        let flow = OutputFlow::default();
+        let mut frames = OutputFrames::new(&flow, false);
+        let chunk = vec![0; FRAME_SIZE];
+        for _ in 0..8 {
+            frames.push(0, &chunk);
+            assert_eq!(1, frames.take(false).len());
+        }
+        frames.push(0, &chunk);
+        assert!(frames.take(false).is_empty());
+        assert!(!frames.has_room(0));
+        flow.ack(1);
+        assert_eq!(1, frames.take(false).len());
+        assert!(frames.has_room(0));
+    }
+
+    #[test]
+    fn reset_opens_window() {
+        let flow = OutputFlow::default();
+        let mut frames = OutputFrames::new(&flow, false);
+        let chunk = vec![0; FRAME_SIZE];
+        for _ in 0..8 {
+            frames.push(0, &chunk);
+            assert_eq!(1, frames.take(false).len());
+        }
+        frames.push(0, &chunk);
+        assert!(frames.take(false).is_empty());
+        flow.reset();
+        assert_eq!(1, frames.take(false).len());
+    }
+}
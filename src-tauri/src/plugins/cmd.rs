@@ -1,18 +1,28 @@
-use std::sync::Arc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
 
+use crate::app_dirs;
 use crate::byte_string::{ByteString, Encoding};
-use crate::conn_pool::ExecuteCommand;
+use crate::command_line::CommandLine;
+use crate::conn_pool::{ExecOptions, ExecuteCommand};
 use crate::device_manager::Device;
 use crate::error::Error;
 use crate::event_channel::{EventChannel, EventHandler};
-use crate::session_manager::{Proc, ProcCallback, ProcData, SessionManager};
+use crate::output_frames::encode_frame;
+use crate::session_manager::{
+    Capture, CaptureInfo, CaptureOptions, Proc, ProcCallback, ProcPty, ProcResult, ProcSignal,
+    SessionManager,
+};
 use crate::spawn_manager::SpawnManager;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use tauri::{
+    ipc::{Channel, InvokeResponseBody},
     plugin::{Builder, TauriPlugin},
     AppHandle, Manager, Runtime, State,
 };
 
+/// Runs `command` and returns its output.
 #[tauri::command]
 async fn exec<R: Runtime>(
     app: AppHandle<R>,
@@ -20,18 +30,126 @@ async fn exec<R: Runtime>(
     command: String,
     stdin: Option<ByteString>,
     encoding: Option<Encoding>,
+    limits: Option<ExecLimits>,
+) -> Result<ExecOutput, Error> {
+    let options = limits.unwrap_or_default().into();
+    exec_blocking(app, device, command, stdin, encoding, options).await
+}
+
+/// Same as [`exec`], with the command line built from separate arguments, so
+/// callers never quote anything themselves.
+#[tauri::command]
+async fn exec_argv<R: Runtime>(
+    app: AppHandle<R>,
+    device: Device,
+    command: CommandLine,
+    stdin: Option<ByteString>,
+    encoding: Option<Encoding>,
+    limits: Option<ExecLimits>,
+) -> Result<ExecOutput, Error> {
+    let command = command.to_shell()?;
+    let options = limits.unwrap_or_default().into();
+    exec_blocking(app, device, command, stdin, encoding, options).await
+}
+
+async fn exec_blocking<R: Runtime>(
+    app: AppHandle<R>,
+    device: Device,
+    command: String,
+    stdin: Option<ByteString>,
+    encoding: Option<Encoding>,
+    options: ExecOptions,
 ) -> Result<ExecOutput, Error> {
     let encoding = encoding.unwrap_or(Encoding::Binary);
     tauri::async_runtime::spawn_blocking(move || {
         let sessions = app.state::<SessionManager>();
         return sessions.with_session(device, move |session| {
-            session.execute_command(&command, stdin.as_ref(), encoding)
+            session.execute_command_with(&command, stdin.as_ref(), encoding, options)
         });
     })
     .await
     .unwrap()
 }
 
+/// Runs one command on many devices at once. Each device's output or error is
+/// sent on the returned channel as soon as it finishes, and the channel closes
+/// once every device is done. Nothing runs until the frontend sends a start
+/// message, so no result is emitted before anyone listens.
+#[tauri::command]
+async fn exec_many<R: Runtime>(
+    app: AppHandle<R>,
+    devices: Vec<Device>,
+    command: String,
+    stdin: Option<ByteString>,
+    encoding: Option<Encoding>,
+    limits: Option<ExecLimits>,
+    concurrency: Option<usize>,
+) -> Result<String, Error> {
+    let channel = EventChannel::<R, ExecManyHandler>::new(app.clone(), "exec-many");
+    let token = channel.token();
+    channel.listen(ExecManyHandler::default());
+    let job = ExecManyJob {
+        command,
+        stdin,
+        encoding: encoding.unwrap_or(Encoding::Binary),
+        options: limits.unwrap_or_default().into(),
+        concurrency: concurrency.unwrap_or(EXEC_MANY_CONCURRENCY).max(1),
+    };
+    tauri::async_runtime::spawn_blocking(move || exec_many_worker(app, channel, devices, job));
+    Ok(token)
+}
+
+/// How many devices [`exec_many`] talks to at once, unless told otherwise.
+const EXEC_MANY_CONCURRENCY: usize = 8;
+
+/// How long [`exec_many`] waits for the frontend to send the start message.
+const EXEC_MANY_START_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn exec_many_worker<R: Runtime>(
+    app: AppHandle<R>,
+    channel: EventChannel<R, ExecManyHandler>,
+    devices: Vec<Device>,
+    job: ExecManyJob,
+) -> Result<(), Error> {
+    let Some(handler) = channel.handler.lock().unwrap().clone() else {
+        return Err(Error::new("exec_many has no handler to start it"));
+    };
+    if !handler.wait() {
+        log::debug!(
+            "exec_many of {} was cancelled or never started",
+            job.command
+        );
+        return Ok(());
+    }
+    let sessions = app.state::<SessionManager>();
+    let workers = job.concurrency.min(devices.len());
+    let queue = Mutex::new(devices.into_iter());
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                if handler.cancelled() {
+                    break;
+                }
+                let Some(device) = queue.lock().unwrap().next() else {
+                    break;
+                };
+                let name = device.name.clone();
+                let result = sessions.with_session(device, |session| {
+                    session.execute_command_with(
+                        &job.command,
+                        job.stdin.as_ref(),
+                        job.encoding,
+                        job.options,
+                    )
+                });
+                channel.rx(ExecManyResult::new(name, result));
+            });
+        }
+    });
+    channel.closed(());
+    Ok(())
+}
+
 #[tauri::command]
 async fn spawn<R: Runtime>(
     app: AppHandle<R>,
@@ -39,31 +157,50 @@ async fn spawn<R: Runtime>(
     device: Device,
     command: String,
     managed: Option<bool>,
+    pty: Option<ProcPty>,
+    capture: Option<CaptureOptions>,
+    on_output: Channel<InvokeResponseBody>,
 ) -> Result<String, Error> {
+    let capture = capture
+        .map(|options| Capture::open(options, &app_dirs::capture_dir(&app)?))
+        .transpose()?;
+    // A capture keeps going when the page reloads, so it isn't managed.
+    let managed = managed.unwrap_or(true) && capture.is_none();
     let channel = EventChannel::<R, ProcEventHandler>::new(app.clone(), "shell-proc");
     let token = channel.token();
-    let proc = Arc::new(sessions.spawn(device, &command));
+    let proc = Arc::new(sessions.spawn(device, &command, pty, capture));
+    if proc.is_capturing() {
+        app.state::<SpawnManager>().add_capture(proc.clone());
+    }
     channel.listen(ProcEventHandler { proc: proc.clone() });
     tauri::async_runtime::spawn_blocking(move || {
-        proc_worker(app, proc, channel, managed.unwrap_or(true))
+        proc_worker(app, proc, channel, on_output, managed)
     });
     Ok(token)
 }
 
+#[tauri::command]
+async fn captures(spawns: State<'_, SpawnManager>) -> Result<Vec<CaptureInfo>, Error> {
+    Ok(spawns.captures())
+}
+
+#[tauri::command]
+async fn stop_capture(spawns: State<'_, SpawnManager>, id: String) -> Result<(), Error> {
+    spawns.stop_capture(&id)
+}
+
 fn proc_worker<R: Runtime>(
     app: AppHandle<R>,
     proc: Arc<Proc>,
     channel: EventChannel<R, ProcEventHandler>,
+    output: Channel<InvokeResponseBody>,
     managed: bool,
 ) -> Result<(), Error> {
     let spawns = app.state::<SpawnManager>();
-    let channel = Arc::new(channel);
     if managed {
         spawns.add_proc(proc.clone());
     }
-    *proc.callback.lock().unwrap() = Some(Box::new(ProcCallbackImpl {
-        channel: channel.clone(),
-    }));
+    *proc.callback.lock().unwrap() = Some(Box::new(ProcCallbackImpl { output }));
     proc.start()?;
     match proc.wait_close(&app.state::<SessionManager>()) {
         Ok(r) => {
@@ -83,46 +220,186 @@ struct ProcEventHandler {
     proc: Arc<Proc>,
 }
 
-struct ProcCallbackImpl<R: Runtime> {
-    channel: Arc<EventChannel<R, ProcEventHandler>>,
+/// Sends output frames as raw bytes, which skips the JSON number arrays
+/// events would need.
+struct ProcCallbackImpl {
+    output: Channel<InvokeResponseBody>,
+}
+
+struct ExecManyJob {
+    command: String,
+    stdin: Option<ByteString>,
+    encoding: Encoding,
+    options: ExecOptions,
+    concurrency: usize,
 }
 
-#[derive(Deserialize)]
-struct TxPayload {
-    data: Option<Vec<u8>>,
+/// Starts the run on the first message, and stops handing out devices on
+/// close. Commands already running are left to finish.
+#[derive(Default)]
+struct ExecManyHandler {
+    /// Whether the run started, and whether it was cancelled.
+    state: (Mutex<(bool, bool)>, Condvar),
 }
 
-#[derive(Serialize, Debug)]
+impl ExecManyHandler {
+    /// Waits for the start message. Returns `false` if the run was cancelled
+    /// first, or if no start message came in [`EXEC_MANY_START_TIMEOUT`],
+    /// which is taken as the frontend having gone away.
+    fn wait(&self) -> bool {
+        let (lock, cvar) = &self.state;
+        let (state, result) = cvar
+            .wait_timeout_while(
+                lock.lock().unwrap(),
+                EXEC_MANY_START_TIMEOUT,
+                |(started, cancelled)| !*started && !*cancelled,
+            )
+            .unwrap();
+        !result.timed_out() && !state.1
+    }
+
+    fn cancelled(&self) -> bool {
+        self.state.0.lock().unwrap().1
+    }
+
+    fn update(&self, update: impl FnOnce(&mut (bool, bool))) {
+        let (lock, cvar) = &self.state;
+        update(&mut lock.lock().unwrap());
+        cvar.notify_all();
+    }
+}
+
+impl EventHandler for ExecManyHandler {
+    fn tx(&self, _payload: Option<&str>) {
+        self.update(|(started, _)| *started = true);
+    }
+
+    fn close(&self, _payload: Option<&str>) {
+        self.update(|(_, cancelled)| *cancelled = true);
+    }
+}
+
+/// What one device of an [`exec_many`] run ended with.
+#[derive(Serialize, Clone, Debug)]
+struct ExecManyResult {
+    device: String,
+    output: Option<ExecOutput>,
+    error: Option<Error>,
+}
+
+impl ExecManyResult {
+    fn new(device: String, result: Result<ExecOutput, Error>) -> Self {
+        let (output, error) = match result {
+            Ok(output) => (Some(output), None),
+            Err(e) => (None, Some(e)),
+        };
+        ExecManyResult {
+            device,
+            output,
+            error,
+        }
+    }
+}
+
+/// What the client sends to a spawned command.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum TxMessage {
+    /// Runs the command, once the client listens for its output.
+    Start,
+    Data {
+        data: Vec<u8>,
+    },
+    /// Closes stdin, so filters reading it can finish.
+    Eof,
+    Signal {
+        signal: ProcSignal,
+        /// Seconds to wait before sending `KILL`.
+        #[serde(rename = "killAfter")]
+        kill_after: Option<u64>,
+    },
+    Resize {
+        rows: u16,
+        cols: u16,
+    },
+    /// The client handled this many output frames since the start.
+    Ack {
+        frames: u64,
+    },
+}
+
+/// How far the `exec` commands let a command go, as the frontend sends it.
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ExecLimits {
+    /// Seconds the command may run.
+    timeout: Option<u64>,
+    /// Bytes kept of each output stream.
+    max_output: Option<usize>,
+    /// Whether a non-zero exit status is returned as output, not as an error.
+    allow_failure: Option<bool>,
+}
+
+impl From<ExecLimits> for ExecOptions {
+    fn from(limits: ExecLimits) -> Self {
+        ExecOptions {
+            timeout: limits.timeout.map(Duration::from_secs),
+            max_output: limits.max_output,
+            allow_failure: limits.allow_failure.unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
 pub(crate) struct ExecOutput {
     pub stdout: ByteString,
     pub stderr: ByteString,
+    /// Whether either stream went past the `max_output` the caller asked for.
+    pub truncated: bool,
+    pub result: ProcResult,
+    /// Seconds from sending the command until it ended.
+    pub duration: f64,
 }
 
-impl<R: Runtime> ProcCallback for ProcCallbackImpl<R> {
+impl ProcCallback for ProcCallbackImpl {
     fn rx(&self, fd: u32, data: &[u8]) {
-        self.channel.rx(ProcData {
-            fd,
-            data: Vec::<u8>::from(data),
-        });
+        let frame = InvokeResponseBody::Raw(encode_frame(fd, data));
+        self.output.send(frame).unwrap_or(());
     }
 }
 
 impl EventHandler for ProcEventHandler {
     fn tx(&self, payload: Option<&str>) {
-        let data = payload
-            .map(|p| {
-                serde_json::from_str::<TxPayload>(p)
-                    .map(|t| t.data)
-                    .ok()
-                    .flatten()
-            })
-            .flatten();
-        if let Some(data) = data {
-            self.proc.write(data).unwrap_or(());
-        } else if !self.proc.is_ready() {
-            self.proc.notify_ready();
-        } else {
-            self.proc.interrupt();
+        let message = match payload.map(serde_json::from_str::<TxMessage>) {
+            Some(Ok(message)) => message,
+            Some(Err(e)) => {
+                log::warn!("{:?} got a bad message: {e}", self.proc);
+                return;
+            }
+            None => {
+                log::warn!("{:?} got an empty message", self.proc);
+                return;
+            }
+        };
+        let result = match message {
+            TxMessage::Start => {
+                self.proc.notify_ready();
+                Ok(())
+            }
+            TxMessage::Data { data } => self.proc.write(data),
+            TxMessage::Eof => self.proc.close_stdin(),
+            TxMessage::Signal { signal, kill_after } => {
+                let kill_after = kill_after.map(Duration::from_secs);
+                self.proc.signal(signal, kill_after)
+            }
+            TxMessage::Resize { rows, cols } => self.proc.resize(rows, cols),
+            TxMessage::Ack { frames } => {
+                self.proc.flow.ack(frames);
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            log::warn!("{:?} failed to handle a message: {e:?}", self.proc);
         }
     }
 }
@@ -130,6 +407,13 @@ impl EventHandler for ProcEventHandler {
 /// Initializes the plugin.
 pub fn plugin<R: Runtime>(name: &'static str) -> TauriPlugin<R> {
     Builder::new(name)
-        .invoke_handler(tauri::generate_handler![exec, spawn])
+        .invoke_handler(tauri::generate_handler![
+            exec,
+            exec_argv,
+            exec_many,
+            spawn,
+            captures,
+            stop_capture
+        ])
         .build()
 }
@@ -1,11 +1,23 @@
 use crate::app_dirs::{self, GetAppSshKeyDir};
+use crate::conn_pool::capabilities::DeviceCapabilities;
+use crate::conn_pool::prompt::{self, AuthPrompt, AuthPrompter};
+use crate::conn_pool::DeviceConnectionPoolStatus;
 use crate::device_manager::privkey::PrivateKeyExt;
-use crate::device_manager::{Device, DeviceCheckConnection, DeviceManager, PrivateKeyInfo};
+use crate::device_manager::{
+    Device, DeviceCheckConnection, DeviceManager, DeviceSettings, PrivateKeyInfo,
+};
+use crate::device_monitor::{DeviceMonitor, DeviceStatus};
 use crate::error::Error;
+use crate::event_channel::{EventChannel, EventHandler};
+use crate::port_forward::PortForwardManager;
+use crate::session_manager::SessionManager;
+use serde::{Deserialize, Serialize};
 use std::io::Read;
+use std::sync::mpsc::{channel, Sender};
+use std::time::Duration;
 use tauri::{
     plugin::{Builder, TauriPlugin},
-    Manager, Runtime,
+    Emitter, Manager, RunEvent, Runtime,
 };
 use tauri::{AppHandle, State};
 use tauri_plugin_fs::{FilePath, Fs, OpenOptions};
@@ -28,13 +40,21 @@ async fn add(manager: State<'_, DeviceManager>, device: Device) -> Result<Device
     manager.add(&device).await
 }
 
+/// Removes a device, and stops the port forwards that use it, which would
+/// otherwise keep its sessions and listening ports open. Its pool goes too, so
+/// a new device under the same name doesn't get the old one's sessions.
 #[tauri::command]
 async fn remove(
     manager: State<'_, DeviceManager>,
+    sessions: State<'_, SessionManager>,
+    forwards: State<'_, PortForwardManager>,
     name: String,
     remove_key: bool,
 ) -> Result<(), Error> {
-    manager.remove(&name, remove_key).await
+    manager.remove(&name, remove_key).await?;
+    forwards.stop_device(&name);
+    sessions.drop_pool(&name);
+    Ok(())
 }
 
 #[tauri::command]
@@ -85,6 +105,80 @@ async fn check_connection(
     manager.check_connection(&host).await
 }
 
+/// Trusts the key the device presented when its last connection failed with
+/// [`Error::HostKeyMismatch`].
+#[tauri::command]
+async fn host_key_accept(manager: State<'_, DeviceManager>, name: String) -> Result<(), Error> {
+    manager.accept_host_key(&name).await
+}
+
+#[tauri::command]
+async fn settings_read(
+    manager: State<'_, DeviceManager>,
+    name: String,
+) -> Result<DeviceSettings, Error> {
+    manager.settings(&name).await
+}
+
+#[tauri::command]
+async fn settings_write(
+    manager: State<'_, DeviceManager>,
+    sessions: State<'_, SessionManager>,
+    forwards: State<'_, PortForwardManager>,
+    name: String,
+    settings: DeviceSettings,
+) -> Result<(), Error> {
+    manager.save_settings(&name, &settings).await?;
+    // A pool reads the settings once, when it is created.
+    sessions.drop_pool(&name);
+    forwards.stop_device(&name);
+    Ok(())
+}
+
+#[tauri::command]
+async fn pool_list(
+    sessions: State<'_, SessionManager>,
+) -> Result<Vec<DeviceConnectionPoolStatus>, Error> {
+    Ok(sessions.pool_status())
+}
+
+/// Drops the sessions of a device, and the port forwards that use them, so the
+/// next command logs in with whatever credentials the device has now.
+#[tauri::command]
+async fn pool_drop(
+    sessions: State<'_, SessionManager>,
+    forwards: State<'_, PortForwardManager>,
+    name: String,
+) -> Result<(), Error> {
+    sessions.drop_pool(&name);
+    forwards.stop_device(&name);
+    Ok(())
+}
+
+/// What the device can do. Only a device no session has probed yet needs a
+/// session to find out.
+#[tauri::command]
+async fn capabilities<R: Runtime>(
+    app: AppHandle<R>,
+    device: Device,
+) -> Result<Option<DeviceCapabilities>, Error> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let sessions = app.state::<SessionManager>();
+        let pool = sessions.pool(device.clone());
+        if let Some(capabilities) = pool.capabilities() {
+            return Ok(Some(capabilities));
+        }
+        sessions.with_session(device, |session| Ok(session.capabilities()))
+    })
+    .await
+    .unwrap()
+}
+
+#[tauri::command]
+async fn monitor_status(monitor: State<'_, DeviceMonitor>) -> Result<Vec<DeviceStatus>, Error> {
+    Ok(monitor.list())
+}
+
 #[tauri::command]
 async fn app_ssh_key_path<R: Runtime>(app: AppHandle<R>) -> Result<String, Error> {
     Ok(app.ensure_app_ssh_key_path()?.to_string_lossy().to_string())
@@ -112,9 +206,78 @@ pub fn plugin<R: Runtime>(name: &'static str) -> TauriPlugin<R> {
             localkey_verify,
             privkey_read,
             check_connection,
+            host_key_accept,
+            settings_read,
+            settings_write,
+            pool_list,
+            pool_drop,
+            capabilities,
+            monitor_status,
             app_ssh_key_path,
             app_ssh_pubkey,
             ssh_key_dir,
         ])
+        .setup(|app, _api| {
+            prompt::set_prompter(Box::new(PluginAuthPrompter { app: app.clone() }));
+            app.state::<DeviceMonitor>().start(app.clone());
+            Ok(())
+        })
+        .on_event(|app, event| {
+            if let RunEvent::Exit = event {
+                app.state::<DeviceMonitor>().stop();
+            }
+        })
         .build()
 }
+
+/// Announces each login question on `device-manager/authPrompt`, with the
+/// token of an event channel that takes the answers.
+struct PluginAuthPrompter<R: Runtime> {
+    app: AppHandle<R>,
+}
+
+#[derive(Serialize, Clone)]
+struct AuthPromptEvent<'a> {
+    token: String,
+    device: &'a str,
+    prompt: &'a AuthPrompt,
+}
+
+#[derive(Deserialize)]
+struct AuthAnswers {
+    answers: Vec<String>,
+}
+
+struct AuthPromptHandler {
+    sender: Sender<Option<Vec<String>>>,
+}
+
+impl<R: Runtime> AuthPrompter for PluginAuthPrompter<R> {
+    fn prompt(&self, device: &str, request: &AuthPrompt, timeout: Duration) -> Option<Vec<String>> {
+        let (sender, receiver) = channel();
+        let events = EventChannel::<R, AuthPromptHandler>::new(self.app.clone(), "auth-prompt");
+        events.listen(AuthPromptHandler { sender });
+        let event = AuthPromptEvent {
+            token: events.token(),
+            device,
+            prompt: request,
+        };
+        self.app.emit("device-manager/authPrompt", event).ok()?;
+        let answers = receiver.recv_timeout(timeout).ok().flatten();
+        events.closed(());
+        answers
+    }
+}
+
+impl EventHandler for AuthPromptHandler {
+    fn tx(&self, payload: Option<&str>) {
+        let answers = payload
+            .and_then(|p| serde_json::from_str::<AuthAnswers>(p).ok())
+            .map(|a| a.answers);
+        self.sender.send(answers).unwrap_or(());
+    }
+
+    fn close(&self, _payload: Option<&str>) {
+        self.sender.send(None).unwrap_or(());
+    }
+}
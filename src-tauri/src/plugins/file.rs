@@ -15,7 +15,7 @@ use crate::device_manager::{Device, DeviceManager};
 use ares_connection_lib::transfer::FileTransfer;
 
 use crate::error::Error;
-use crate::remote_files::serve;
+use crate::remote_files::{exec, serve};
 use crate::remote_files::{FileItem, PermInfo};
 use crate::session_manager::SessionManager;
 
@@ -38,7 +38,9 @@ async fn ls<R: Runtime>(
     tauri::async_runtime::spawn_blocking(move || {
         let sessions = app.state::<SessionManager>();
         return sessions.with_session(device, |session| {
-            let sftp = session.sftp()?;
+            let Some(sftp) = session.try_sftp()? else {
+                return exec::read_dir(session, &path);
+            };
             let entries = sftp.read_dir(&path)?;
             let user = session.user.as_ref();
             return Ok(entries
@@ -135,13 +137,13 @@ async fn get<R: Runtime>(
             // Only SFTP can tell us the size up front. Without it the progress
             // events carry a total of 0, which the frontend already tolerates.
             let total = session
-                .maybe_sftp()
-                .and_then(|sftp| sftp.open(&path, OpenFlags::READ_ONLY, 0)?.metadata())
-                .ok()
+                .try_sftp()?
+                .and_then(|sftp| sftp.open(&path, OpenFlags::READ_ONLY, 0).ok())
+                .and_then(|file| file.metadata().ok())
                 .and_then(|meta| meta.len())
                 .unwrap_or_default() as usize;
             // The shared FileTransfer streams the file over an exec channel when
-            // the device has no SFTP.
+            // the device has no SFTP, which try_sftp has found out by now.
             session.get(&path, &mut file, |copied| {
                 let _ = on_progress.send(CopyProgress { copied, total });
             })?;
@@ -174,7 +176,8 @@ async fn put<R: Runtime>(
             })?;
             let total = file.metadata().unwrap().len() as usize;
             // The shared FileTransfer streams the file over an exec channel when
-            // the device has no SFTP.
+            // the device has no SFTP, so find out before it tries.
+            session.try_sftp()?;
             session.put(&mut file, &path, |copied| {
                 let _ = on_progress.send(CopyProgress { copied, total });
             })?;
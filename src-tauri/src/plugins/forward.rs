@@ -0,0 +1,90 @@
+use tauri::plugin::{Builder, TauriPlugin};
+use tauri::{AppHandle, Manager, Runtime, State};
+
+use crate::device_manager::Device;
+use crate::error::Error;
+use crate::port_forward::{PortForwardInfo, PortForwardManager};
+use crate::session_manager::SessionManager;
+
+/// Forwards a local port to `remote_port` on the device, or on `remote_host`
+/// as the device sees it. Without `local_port`, any free port is picked.
+#[tauri::command]
+async fn start(
+    manager: State<'_, PortForwardManager>,
+    sessions: State<'_, SessionManager>,
+    device: Device,
+    local_port: Option<u16>,
+    remote_host: Option<String>,
+    remote_port: u16,
+) -> Result<PortForwardInfo, Error> {
+    let name = device.name.clone();
+    manager.start(
+        sessions.pool(device),
+        &name,
+        local_port.unwrap_or(0),
+        remote_host.as_deref().unwrap_or("127.0.0.1"),
+        remote_port,
+    )
+}
+
+/// Forwards `remote_port` on the device, any free one if it is 0, to
+/// `local_port` on this machine, or on `local_host` as this machine sees it.
+#[tauri::command]
+async fn start_reverse<R: Runtime>(
+    app: AppHandle<R>,
+    device: Device,
+    remote_port: Option<u16>,
+    local_host: Option<String>,
+    local_port: u16,
+) -> Result<PortForwardInfo, Error> {
+    // Logs in and asks the device to listen, which may take a while.
+    tauri::async_runtime::spawn_blocking(move || {
+        let name = device.name.clone();
+        let pool = app.state::<SessionManager>().pool(device);
+        app.state::<PortForwardManager>().start_reverse(
+            pool,
+            &name,
+            remote_port.unwrap_or(0),
+            local_host.as_deref().unwrap_or("127.0.0.1"),
+            local_port,
+        )
+    })
+    .await
+    .unwrap()
+}
+
+/// Starts a SOCKS5 proxy on this machine that connects from the device.
+/// Without `local_port`, any free port is picked.
+#[tauri::command]
+async fn start_socks(
+    manager: State<'_, PortForwardManager>,
+    sessions: State<'_, SessionManager>,
+    device: Device,
+    local_port: Option<u16>,
+) -> Result<PortForwardInfo, Error> {
+    let name = device.name.clone();
+    manager.start_socks(sessions.pool(device), &name, local_port.unwrap_or(0))
+}
+
+#[tauri::command]
+async fn list(manager: State<'_, PortForwardManager>) -> Result<Vec<PortForwardInfo>, Error> {
+    Ok(manager.list())
+}
+
+#[tauri::command]
+async fn stop(manager: State<'_, PortForwardManager>, id: String) -> Result<(), Error> {
+    manager.stop(&id)
+}
+
+/// Initializes the plugin.
+pub fn plugin<R: Runtime>(name: &'static str) -> TauriPlugin<R> {
+    Builder::new(name)
+        .invoke_handler(tauri::generate_handler![
+            start,
+            start_reverse,
+            start_socks,
+            list,
+            stop
+        ])
+        .build()
+}
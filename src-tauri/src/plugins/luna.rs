@@ -0,0 +1,167 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+use serde_json::Value;
+use tauri::plugin::{Builder, TauriPlugin};
+use tauri::{AppHandle, Manager, Runtime};
+
+use crate::device_manager::Device;
+use crate::error::Error;
+use crate::event_channel::{EventChannel, EventHandler};
+use crate::luna;
+use crate::session_manager::{Proc, ProcCallback, ProcResult, SessionManager};
+use crate::spawn_manager::SpawnManager;
+
+/// Calls `uri` with `payload`, on the public bus unless `public` is `false`.
+#[tauri::command]
+async fn call<R: Runtime>(
+    app: AppHandle<R>,
+    device: Device,
+    uri: String,
+    payload: Option<Value>,
+    public: Option<bool>,
+) -> Result<Value, Error> {
+    let payload = payload.unwrap_or_else(|| Value::Object(Default::default()));
+    tauri::async_runtime::spawn_blocking(move || {
+        let sessions = app.state::<SessionManager>();
+        sessions.with_session(device, |session| {
+            luna::call(session, &uri, &payload, public.unwrap_or(true))
+        })
+    })
+    .await
+    .unwrap()
+}
+
+/// Subscribes to `uri`, and sends each response on the returned channel as it
+/// comes. Nothing is sent until the frontend sends a start message, and
+/// closing the channel from the frontend ends the subscription.
+#[tauri::command]
+async fn subscribe<R: Runtime>(
+    app: AppHandle<R>,
+    device: Device,
+    uri: String,
+    payload: Option<Value>,
+    public: Option<bool>,
+) -> Result<String, Error> {
+    let payload = payload.unwrap_or_else(|| Value::Object(Default::default()));
+    let command = luna::command(&uri, &payload, public.unwrap_or(true), true);
+    let sessions = app.state::<SessionManager>();
+    let proc = Arc::new(sessions.spawn(device, &command, None, None));
+    let channel = Arc::new(EventChannel::<R, SubscriptionHandler>::new(
+        app.clone(),
+        "luna-subscription",
+    ));
+    let token = channel.token();
+    channel.listen(SubscriptionHandler { proc: proc.clone() });
+    tauri::async_runtime::spawn_blocking(move || subscription_worker(app, proc, channel));
+    Ok(token)
+}
+
+fn subscription_worker<R: Runtime>(
+    app: AppHandle<R>,
+    proc: Arc<Proc>,
+    channel: Arc<EventChannel<R, SubscriptionHandler>>,
+) {
+    app.state::<SpawnManager>().add_proc(proc.clone());
+    let stderr = Arc::new(Mutex::new(Vec::new()));
+    let refused = Arc::new(Mutex::new(None));
+    *proc.callback.lock().unwrap() = Some(Box::new(SubscriptionCallback {
+        channel: channel.clone(),
+        proc: Arc::downgrade(&proc),
+        responses: Mutex::default(),
+        stderr: stderr.clone(),
+        refused: refused.clone(),
+        frames: AtomicU64::default(),
+    }));
+    let result = proc
+        .start()
+        .and_then(|_| proc.wait_close(&app.state::<SessionManager>()));
+    proc.callback.lock().unwrap().take();
+    if let Some(e) = refused.lock().unwrap().take() {
+        log::warn!("{proc:?} was refused with {e:?}");
+        channel.closed(e);
+        return;
+    }
+    match result {
+        // Ended by the frontend, or by the service.
+        Ok(ProcResult::Exit { status: 0 } | ProcResult::Signal { .. } | ProcResult::Closed) => {
+            channel.closed(());
+        }
+        Ok(ProcResult::Exit { status }) => {
+            let e = luna::map_exit(Error::ExitStatus {
+                message: format!("Subscription ended with status {status}"),
+                command: proc.command.clone(),
+                exit_code: status,
+                signal: None,
+                stderr: std::mem::take(&mut stderr.lock().unwrap()),
+                unhandled: true,
+            });
+            log::warn!("{proc:?} closed with {e:?}");
+            channel.closed(e);
+        }
+        Err(e) => {
+            log::warn!("{proc:?} closed with {e:?}");
+            channel.closed(e);
+        }
+    }
+}
+
+struct SubscriptionHandler {
+    proc: Arc<Proc>,
+}
+
+impl EventHandler for SubscriptionHandler {
+    fn tx(&self, _payload: Option<&str>) {
+        self.proc.notify_ready();
+    }
+
+    fn close(&self, _payload: Option<&str>) {
+        self.proc.interrupt();
+    }
+}
+
+/// Sends each response as a JSON object. Responses are handled as soon as
+/// they come, so every frame is acknowledged right away. A refused
+/// subscription is kept in `refused` and ended, for the worker to close the
+/// channel with.
+struct SubscriptionCallback<R: Runtime> {
+    channel: Arc<EventChannel<R, SubscriptionHandler>>,
+    proc: Weak<Proc>,
+    responses: Mutex<luna::Responses>,
+    stderr: Arc<Mutex<Vec<u8>>>,
+    refused: Arc<Mutex<Option<Error>>>,
+    frames: AtomicU64,
+}
+
+impl<R: Runtime> ProcCallback for SubscriptionCallback<R> {
+    fn rx(&self, fd: u32, data: &[u8]) {
+        if fd == 0 {
+            match self.responses.lock().unwrap().push(data) {
+                Ok(responses) => {
+                    for response in responses {
+                        self.channel.rx(response);
+                    }
+                }
+                Err(e) => {
+                    *self.refused.lock().unwrap() = Some(e);
+                    if let Some(proc) = self.proc.upgrade() {
+                        proc.interrupt();
+                    }
+                }
+            }
+        } else {
+            self.stderr.lock().unwrap().extend_from_slice(data);
+        }
+        let frames = self.frames.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(proc) = self.proc.upgrade() {
+            proc.flow.ack(frames);
+        }
+    }
+}
+
+/// Initializes the plugin.
+pub fn plugin<R: Runtime>(name: &'static str) -> TauriPlugin<R> {
+    Builder::new(name)
+        .invoke_handler(tauri::generate_handler![call, subscribe])
+        .build()
+}
@@ -2,5 +2,7 @@ pub mod cmd;
 pub mod device;
 pub mod devmode;
 pub mod file;
+pub mod forward;
 pub mod local_file;
+pub mod luna;
 pub mod shell;
@@ -1,30 +1,61 @@
+use tauri::ipc::{Channel, InvokeResponseBody};
 use tauri::plugin::{Builder, TauriPlugin};
 use tauri::{AppHandle, Emitter, Manager, Runtime, State};
 
 use crate::device_manager::Device;
 use crate::error::Error;
-use crate::shell_manager::{
-    ShellCallback, ShellData, ShellInfo, ShellManager, ShellScreen, ShellToken,
-};
+use crate::output_frames::encode_frame;
+use crate::session_manager::SessionManager;
+use crate::shell_manager::{ShellCallback, ShellInfo, ShellManager, ShellScreen, ShellToken};
 
 #[tauri::command]
 fn open<R: Runtime>(
     app: AppHandle<R>,
     manager: State<'_, ShellManager>,
+    sessions: State<'_, SessionManager>,
     device: Device,
     cols: u16,
     rows: u16,
     dumb: Option<bool>,
+    reconnect: Option<bool>,
+    on_output: Channel<InvokeResponseBody>,
 ) -> Result<ShellInfo, Error> {
-    let shell = manager.open(device, rows, cols, dumb.unwrap_or(false));
+    let pool = sessions.pool(device.clone());
+    let shell = manager.open(
+        device,
+        pool,
+        rows,
+        cols,
+        dumb.unwrap_or(false),
+        reconnect.unwrap_or(false),
+    );
     *shell.callback.lock().unwrap() = Some(Box::new(PluginShellCb::<R> {
         token: shell.token.clone(),
         app: app.clone(),
+        output: on_output,
     }));
     app.emit("shell-opened", &shell.token).unwrap_or(());
     Ok(shell.info())
 }
 
+/// Sends the output of a shell on `on_output` from now on, for a page that
+/// reloaded and lost the channel it opened the shell with.
+#[tauri::command]
+fn attach<R: Runtime>(
+    app: AppHandle<R>,
+    manager: State<'_, ShellManager>,
+    token: ShellToken,
+    on_output: Channel<InvokeResponseBody>,
+) -> Result<ShellInfo, Error> {
+    let shell = manager.get(&token)?;
+    shell.attach(Box::new(PluginShellCb::<R> {
+        token,
+        app,
+        output: on_output,
+    }));
+    Ok(shell.info())
+}
+
 #[tauri::command]
 async fn close<R: Runtime>(
     app: AppHandle<R>,
@@ -42,6 +73,14 @@ fn write(manager: State<'_, ShellManager>, token: ShellToken, data: Vec<u8>) ->
     shell.write(&data)
 }
 
+/// Tells the shell the frontend handled `frames` output frames since it
+/// opened.
+#[tauri::command]
+fn ack(manager: State<'_, ShellManager>, token: ShellToken, frames: u64) -> Result<(), Error> {
+    manager.get(&token)?.flow.ack(frames);
+    Ok(())
+}
+
 #[tauri::command]
 async fn resize(
     manager: State<'_, ShellManager>,
@@ -71,7 +110,7 @@ async fn list(manager: State<'_, ShellManager>) -> Result<Vec<ShellInfo>, Error>
 pub fn plugin<R: Runtime>(name: &'static str) -> TauriPlugin<R> {
     Builder::new(name)
         .invoke_handler(tauri::generate_handler![
-            open, close, write, resize, screen, list
+            open, attach, close, write, ack, resize, screen, list
         ])
         .build()
 }
@@ -79,6 +118,7 @@ pub fn plugin<R: Runtime>(name: &'static str) -> TauriPlugin<R> {
 struct PluginShellCb<R: Runtime> {
     token: ShellToken,
     app: AppHandle<R>,
+    output: Channel<InvokeResponseBody>,
 }
 
 impl<R: Runtime> ShellCallback for PluginShellCb<R> {
@@ -87,12 +127,8 @@ impl<R: Runtime> ShellCallback for PluginShellCb<R> {
     }
 
     fn rx(&self, fd: u32, data: &[u8]) {
-        let payload = ShellData {
-            token: self.token.clone(),
-            data: Vec::from(data),
-            fd,
-        };
-        self.app.emit("shell-rx", payload).unwrap_or(());
+        let frame = InvokeResponseBody::Raw(encode_frame(fd, data));
+        self.output.send(frame).unwrap_or(());
     }
 
     fn closed(&self, removed: bool) {
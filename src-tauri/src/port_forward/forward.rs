@@ -0,0 +1,274 @@
+use std::fmt::{Debug, Formatter};
+use std::io::ErrorKind;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use libssh_rs::Channel;
+
+use crate::conn_pool::{copy, DeviceConnection, POLL_INTERVAL};
+use crate::error::Error;
+use crate::port_forward::{ForwardDirection, PortForward, PortForwardInfo};
+
+/// How often the listener looks for new connections, and whether it was
+/// stopped.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+
+impl PortForward {
+    pub fn info(&self) -> PortForwardInfo {
+        PortForwardInfo {
+            id: self.id.to_string(),
+            device: self.device.clone(),
+            direction: self.direction,
+            local_host: self.local_host.clone(),
+            local_port: self.local_port,
+            remote_host: self.remote_host.clone(),
+            remote_port: self.remote_port,
+            connections: self.connections.lock().unwrap().len(),
+            bytes_sent: self.traffic.sent.load(Ordering::Relaxed),
+            bytes_received: self.traffic.received.load(Ordering::Relaxed),
+            age: self.created_at.elapsed().as_secs_f64(),
+        }
+    }
+
+    /// Closes the listener and every connection made through it.
+    pub(crate) fn stop(&self) {
+        *self.stopped.lock().unwrap() = true;
+        for (_, socket) in self.connections.lock().unwrap().drain() {
+            socket.shutdown(Shutdown::Both).unwrap_or(());
+        }
+    }
+
+    fn is_stopped(&self) -> bool {
+        *self.stopped.lock().unwrap()
+    }
+
+    /// Accepts connections for a local or dynamic forward.
+    pub(super) fn run_local(self: Arc<Self>, listener: TcpListener) {
+        while !self.is_stopped() {
+            let socket = match listener.accept() {
+                Ok((socket, _)) => socket,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(ACCEPT_INTERVAL);
+                    continue;
+                }
+                Err(e) => {
+                    log::warn!("{self:?} failed to accept a connection: {e:?}");
+                    break;
+                }
+            };
+            self.spawn_connection(move |forward| {
+                // Accepted sockets inherit non-blocking mode on some platforms.
+                socket.set_nonblocking(false)?;
+                if forward.direction == ForwardDirection::Dynamic {
+                    return forward.serve_socks(socket);
+                }
+                let (conn, channel) =
+                    forward.open_channel(&forward.remote_host, forward.remote_port)?;
+                forward.serve(&channel, socket)?;
+                conn.mark_last_ok();
+                Ok(())
+            });
+        }
+        self.finish();
+    }
+
+    /// Accepts the channels of the listening session `conn`, which closes
+    /// with its listener once the forward ends.
+    pub(super) fn run_remote(self: Arc<Self>, conn: DeviceConnection) {
+        while !self.is_stopped() {
+            let channel = match conn.accept_forward(ACCEPT_INTERVAL) {
+                Ok((_, channel)) => channel,
+                // Nothing came in before the timeout.
+                Err(_) if conn.is_connected() => continue,
+                Err(e) => {
+                    log::warn!("{self:?} lost its session: {e:?}");
+                    break;
+                }
+            };
+            self.spawn_connection(move |forward| {
+                let socket = TcpStream::connect((forward.local_host.as_str(), forward.local_port))
+                    .inspect_err(|_| channel.close().unwrap_or(()))?;
+                forward.serve(&channel, socket)
+            });
+        }
+        self.finish();
+    }
+
+    /// Runs one connection on a thread of its own.
+    fn spawn_connection<F>(self: &Arc<Self>, connection: F)
+    where
+        F: FnOnce(&PortForward) -> Result<(), Error> + Send + 'static,
+    {
+        let forward = self.clone();
+        let name = format!("forward-{}-{}-conn", self.device, self.local_port);
+        let spawned = thread::Builder::new().name(name).spawn(move || {
+            if let Err(e) = connection(&forward) {
+                log::warn!("{forward:?} closed a connection with {e:?}");
+            }
+        });
+        if let Err(e) = spawned {
+            log::warn!("{self:?} failed to start a connection: {e:?}");
+        }
+    }
+
+    /// Also ends a forward whose listener failed, not only a stopped one.
+    fn finish(&self) {
+        self.stop();
+        self.forwards.lock().unwrap().remove(&self.id);
+        log::info!("Stopped {self:?}");
+    }
+
+    /// Copies between the channel and the local socket until either closes.
+    pub(super) fn serve(&self, channel: &Channel, socket: TcpStream) -> Result<(), Error> {
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let id = {
+            let mut next = self.next_connection.lock().unwrap();
+            *next += 1;
+            *next
+        };
+        self.connections
+            .lock()
+            .unwrap()
+            .insert(id, socket.try_clone()?);
+        // Stopped while the connection opened, after stop() closed the others.
+        if self.is_stopped() {
+            socket.shutdown(Shutdown::Both).unwrap_or(());
+        }
+        let result = copy(channel, socket, &self.traffic);
+        self.connections.lock().unwrap().remove(&id);
+        channel.close().unwrap_or(());
+        result
+    }
+
+    /// Opens a tunnel to `host:port` on the device's shared session, so a
+    /// browser opening many connections doesn't log in for each one.
+    pub(super) fn open_channel(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> Result<(Arc<DeviceConnection>, Channel), Error> {
+        self.pool.open_shared(|conn| {
+            let channel = conn.new_channel()?;
+            channel.open_forward(host, port, "127.0.0.1", 0)?;
+            Ok(channel)
+        })
+    }
+}
+
+impl Debug for PortForward {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "PortForward {{ device={}, direction={:?}, local={}:{}, remote={}:{} }}",
+            self.device,
+            self.direction,
+            self.local_host,
+            self.local_port,
+            self.remote_host,
+            self.remote_port
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::byte_string::Encoding;
+    use crate::conn_pool::{DeviceConnectionPool, ExecOptions, ExecuteCommand};
+    use crate::port_forward::PortForwardManager;
+    use crate::tests::common::{test_device, SshContainer, TempDir};
+
+    #[test]
+    fn forward_to_sshd() {
+        let sshd = SshContainer::new();
+        let device = test_device(sshd.wait());
+        let manager = PortForwardManager::default();
+        let conf_dir = TempDir::new();
+        let pool = DeviceConnectionPool::new(device, None, Some(conf_dir.path()));
+        let info = manager
+            .start(pool, "test", 0, "127.0.0.1", 22)
+            .expect("Failed to start forward");
+        let mut socket = TcpStream::connect(("127.0.0.1", info.local_port)).unwrap();
+        let mut banner = [0; 8];
+        socket.read_exact(&mut banner).unwrap();
+        assert_eq!(b"SSH-2.0-", &banner);
+        assert_eq!(1, manager.list().len());
+
+        manager.stop(&info.id).unwrap();
+        assert!(manager.list().is_empty());
+    }
+
+    #[test]
+    fn reverse_forward_from_sshd() {
+        let sshd = SshContainer::new();
+        let device = test_device(sshd.wait());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let local_port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            socket.write_all(b"hello\n").unwrap();
+        });
+        let manager = PortForwardManager::default();
+        let conf_dir = TempDir::new();
+        let pool = DeviceConnectionPool::new(device, None, Some(conf_dir.path()));
+        let info = manager
+            .start_reverse(pool.clone(), "test", 0, "127.0.0.1", local_port)
+            .expect("Failed to start reverse forward");
+        // The listening session stays out of the pool.
+        assert_eq!(0, pool.status("test").connections);
+        let output = pool
+            .get()
+            .unwrap()
+            .execute_command_with(
+                &format!("nc 127.0.0.1 {} </dev/null", info.remote_port),
+                None,
+                Encoding::String,
+                ExecOptions {
+                    timeout: Some(Duration::from_secs(10)),
+                    ..ExecOptions::default()
+                },
+            )
+            .expect("Failed to connect through the forward");
+        server.join().unwrap();
+        assert_eq!(b"hello\n", output.stdout.as_ref());
+        // The count goes up after the write, which may be after nc is done.
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(6, manager.list()[0].bytes_sent);
+
+        manager.stop(&info.id).unwrap();
+        assert!(manager.list().is_empty());
+    }
+
+    #[test]
+    fn socks_to_sshd() {
+        let sshd = SshContainer::new();
+        let device = test_device(sshd.wait());
+        let manager = PortForwardManager::default();
+        let conf_dir = TempDir::new();
+        let pool = DeviceConnectionPool::new(device, None, Some(conf_dir.path()));
+        let info = manager
+            .start_socks(pool, "test", 0)
+            .expect("Failed to start SOCKS proxy");
+        let mut socket = TcpStream::connect(("127.0.0.1", info.local_port)).unwrap();
+        socket.write_all(&[5, 1, 0]).unwrap();
+        let mut method = [0; 2];
+        socket.read_exact(&mut method).unwrap();
+        assert_eq!([5, 0], method);
+        // CONNECT to localhost:22, by name.
+        let request = [&[5, 1, 0, 3, 9][..], b"localhost", &22u16.to_be_bytes()].concat();
+        socket.write_all(&request).unwrap();
+        let mut reply = [0; 10];
+        socket.read_exact(&mut reply).unwrap();
+        assert_eq!(0, reply[1]);
+        let mut banner = [0; 8];
+        socket.read_exact(&mut banner).unwrap();
+        assert_eq!(b"SSH-2.0-", &banner);
+    }
+}
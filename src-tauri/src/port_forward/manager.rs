@@ -0,0 +1,197 @@
+use std::net::{Ipv4Addr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use uuid::Uuid;
+
+use crate::conn_pool::{DeviceConnectionPool, Traffic};
+use crate::error::Error;
+use crate::port_forward::{
+    ForwardDirection, ForwardsMap, PortForward, PortForwardInfo, PortForwardManager,
+};
+
+impl PortForwardManager {
+    /// Listens on `local_port` of the loopback interface, 0 for any free one,
+    /// and forwards each connection to `remote_host:remote_port` as seen from
+    /// the device.
+    pub fn start(
+        &self,
+        pool: DeviceConnectionPool,
+        device: &str,
+        local_port: u16,
+        remote_host: &str,
+        remote_port: u16,
+    ) -> Result<PortForwardInfo, Error> {
+        let listener = bind(local_port)?;
+        let forward = self.add(PortForward::new(
+            self.forwards.clone(),
+            pool,
+            device,
+            ForwardDirection::Local,
+            ("127.0.0.1", listener.local_addr()?.port()),
+            (remote_host, remote_port),
+        ));
+        let worker = forward.clone();
+        self.spawn(&forward, move || worker.run_local(listener))?;
+        Ok(forward.info())
+    }
+
+    /// Listens on `remote_port` of the device's loopback interface, 0 for any
+    /// free one, and forwards each connection to `local_host:local_port` as
+    /// seen from this machine.
+    pub fn start_reverse(
+        &self,
+        pool: DeviceConnectionPool,
+        device: &str,
+        remote_port: u16,
+        local_host: &str,
+        local_port: u16,
+    ) -> Result<PortForwardInfo, Error> {
+        // Channels of a listening session all go to whoever accepts them, so
+        // the listener gets a session of its own rather than the shared one.
+        // It is held for as long as the forward runs, so it stays out of the
+        // pool too.
+        let conn = pool.dedicated()?;
+        let remote_port = conn.listen_forward(Some("127.0.0.1"), remote_port)?;
+        let forward = self.add(PortForward::new(
+            self.forwards.clone(),
+            pool,
+            device,
+            ForwardDirection::Remote,
+            (local_host, local_port),
+            ("127.0.0.1", remote_port),
+        ));
+        let worker = forward.clone();
+        self.spawn(&forward, move || worker.run_remote(conn))?;
+        Ok(forward.info())
+    }
+
+    /// Listens on `local_port` of the loopback interface, 0 for any free one,
+    /// as a SOCKS5 proxy that connects from the device.
+    pub fn start_socks(
+        &self,
+        pool: DeviceConnectionPool,
+        device: &str,
+        local_port: u16,
+    ) -> Result<PortForwardInfo, Error> {
+        let listener = bind(local_port)?;
+        let forward = self.add(PortForward::new(
+            self.forwards.clone(),
+            pool,
+            device,
+            ForwardDirection::Dynamic,
+            ("127.0.0.1", listener.local_addr()?.port()),
+            ("", 0),
+        ));
+        let worker = forward.clone();
+        self.spawn(&forward, move || worker.run_local(listener))?;
+        Ok(forward.info())
+    }
+
+    /// All forwards, sorted by device name and local port.
+    pub fn list(&self) -> Vec<PortForwardInfo> {
+        let mut list: Vec<PortForwardInfo> = self
+            .forwards
+            .lock()
+            .expect("Failed to lock PortForwardManager::forwards")
+            .values()
+            .map(|forward| forward.info())
+            .collect();
+        list.sort_by(|a, b| (&a.device, a.local_port).cmp(&(&b.device, b.local_port)));
+        list
+    }
+
+    pub fn stop(&self, id: &str) -> Result<(), Error> {
+        let forward = Uuid::parse_str(id).ok().and_then(|id| {
+            self.forwards
+                .lock()
+                .expect("Failed to lock PortForwardManager::forwards")
+                .remove(&id)
+        });
+        let Some(forward) = forward else {
+            return Err(Error::NotFound);
+        };
+        forward.stop();
+        Ok(())
+    }
+
+    /// Stops every forward of a device, for when its pool is dropped.
+    pub fn stop_device(&self, device: &str) {
+        let mut forwards = self
+            .forwards
+            .lock()
+            .expect("Failed to lock PortForwardManager::forwards");
+        let ids: Vec<Uuid> = forwards
+            .values()
+            .filter(|forward| forward.device == device)
+            .map(|forward| forward.id)
+            .collect();
+        for id in ids {
+            if let Some(forward) = forwards.remove(&id) {
+                forward.stop();
+            }
+        }
+    }
+
+    fn add(&self, forward: PortForward) -> Arc<PortForward> {
+        let forward = Arc::new(forward);
+        self.forwards
+            .lock()
+            .expect("Failed to lock PortForwardManager::forwards")
+            .insert(forward.id, forward.clone());
+        forward
+    }
+
+    fn spawn<F>(&self, forward: &Arc<PortForward>, worker: F) -> Result<(), Error>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let name = format!("forward-{}-{}", forward.device, forward.local_port);
+        if let Err(e) = thread::Builder::new().name(name).spawn(worker) {
+            self.forwards
+                .lock()
+                .expect("Failed to lock PortForwardManager::forwards")
+                .remove(&forward.id);
+            return Err(e.into());
+        }
+        log::info!("Started {forward:?}");
+        Ok(())
+    }
+}
+
+impl PortForward {
+    fn new(
+        forwards: Arc<Mutex<ForwardsMap>>,
+        pool: DeviceConnectionPool,
+        device: &str,
+        direction: ForwardDirection,
+        (local_host, local_port): (&str, u16),
+        (remote_host, remote_port): (&str, u16),
+    ) -> PortForward {
+        PortForward {
+            id: Uuid::new_v4(),
+            device: device.to_string(),
+            direction,
+            local_host: local_host.to_string(),
+            local_port,
+            remote_host: remote_host.to_string(),
+            remote_port,
+            created_at: Instant::now(),
+            pool,
+            traffic: Traffic::default(),
+            stopped: Mutex::new(false),
+            connections: Mutex::default(),
+            next_connection: Mutex::default(),
+            forwards,
+        }
+    }
+}
+
+/// Listens on the loopback interface only, since anyone who can connect gets
+/// into the device's network.
+fn bind(port: u16) -> Result<TcpListener, Error> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
@@ -0,0 +1,78 @@
+//! Port forwarding in both directions. A local forward, like OpenSSH's `-L`,
+//! is a port on this machine that reaches a port on the device, or on a host
+//! the device can reach. A remote forward, like `-R`, is a port on the device
+//! that reaches a host and port this machine can reach. A dynamic forward,
+//! like `-D`, is a SOCKS5 proxy on this machine that reaches whatever the
+//! device can reach.
+
+use std::collections::HashMap;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::conn_pool::{DeviceConnectionPool, Traffic};
+
+mod forward;
+mod manager;
+mod socks;
+
+#[derive(Default)]
+pub struct PortForwardManager {
+    forwards: Arc<Mutex<ForwardsMap>>,
+}
+
+type ForwardsMap = HashMap<Uuid, Arc<PortForward>>;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum ForwardDirection {
+    /// Connections come in on this machine and go out from the device.
+    Local,
+    /// Connections come in on the device and go out from this machine.
+    Remote,
+    /// SOCKS5 connections come in on this machine and go out from the device
+    /// to wherever each one asks for.
+    Dynamic,
+}
+
+pub struct PortForward {
+    id: Uuid,
+    device: String,
+    direction: ForwardDirection,
+    local_host: String,
+    local_port: u16,
+    remote_host: String,
+    remote_port: u16,
+    created_at: Instant,
+    pool: DeviceConnectionPool,
+    traffic: Traffic,
+    stopped: Mutex<bool>,
+    /// The local end of each open connection, so stopping can close them.
+    connections: Mutex<HashMap<u64, TcpStream>>,
+    next_connection: Mutex<u64>,
+    forwards: Arc<Mutex<ForwardsMap>>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PortForwardInfo {
+    pub id: String,
+    pub device: String,
+    pub direction: ForwardDirection,
+    pub local_host: String,
+    pub local_port: u16,
+    /// Empty for a dynamic forward.
+    pub remote_host: String,
+    /// 0 for a dynamic forward.
+    pub remote_port: u16,
+    /// How many connections are open through the forward.
+    pub connections: usize,
+    /// Bytes sent from this machine to the device.
+    pub bytes_sent: u64,
+    /// Bytes received from the device.
+    pub bytes_received: u64,
+    /// Seconds since the forward started.
+    pub age: f64,
+}
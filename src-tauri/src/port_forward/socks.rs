@@ -0,0 +1,102 @@
+//! The SOCKS5 side of a dynamic forward (RFC 1928). Only CONNECT without
+//! authentication, which is what browsers and `curl --socks5-hostname` use.
+
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, TcpStream};
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+use crate::port_forward::PortForward;
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const CONNECT: u8 = 1;
+const ADDRESS_IPV4: u8 = 1;
+const ADDRESS_DOMAIN: u8 = 3;
+const ADDRESS_IPV6: u8 = 4;
+const SUCCEEDED: u8 = 0;
+const GENERAL_FAILURE: u8 = 1;
+const COMMAND_NOT_SUPPORTED: u8 = 7;
+const ADDRESS_NOT_SUPPORTED: u8 = 8;
+
+/// How long a client gets to say where it wants to go.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl PortForward {
+    pub(super) fn serve_socks(&self, mut socket: TcpStream) -> Result<(), Error> {
+        socket.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let (host, port) = handshake(&mut socket)?;
+        let (conn, channel) = match self.open_channel(&host, port) {
+            Ok(opened) => opened,
+            Err(e) => {
+                log::info!("{self:?} failed to connect to {host}:{port}: {e:?}");
+                reply(&mut socket, GENERAL_FAILURE).unwrap_or(());
+                return Err(e);
+            }
+        };
+        reply(&mut socket, SUCCEEDED)?;
+        log::info!("{self:?} connected to {host}:{port}");
+        let started_at = Instant::now();
+        let result = self.serve(&channel, socket);
+        conn.mark_last_ok();
+        log::info!(
+            "{self:?} closed the connection to {host}:{port} after {:.1}s",
+            started_at.elapsed().as_secs_f64()
+        );
+        result
+    }
+}
+
+/// Reads the greeting and the request, and returns the host and port the
+/// client asked for. Hosts stay names, so the device resolves them.
+fn handshake(socket: &mut TcpStream) -> Result<(String, u16), Error> {
+    let [version, methods] = read_array(socket)?;
+    if version != VERSION {
+        return Err(Error::new(format!("Unsupported SOCKS version {version}")));
+    }
+    let mut methods = vec![0; methods as usize];
+    socket.read_exact(&mut methods)?;
+    if !methods.contains(&NO_AUTH) {
+        socket.write_all(&[VERSION, NO_ACCEPTABLE_METHOD])?;
+        return Err(Error::new("SOCKS client requires authentication"));
+    }
+    socket.write_all(&[VERSION, NO_AUTH])?;
+
+    let [_, command, _, address_type] = read_array(socket)?;
+    if command != CONNECT {
+        reply(socket, COMMAND_NOT_SUPPORTED)?;
+        return Err(Error::new(format!("Unsupported SOCKS command {command}")));
+    }
+    let host = match address_type {
+        ADDRESS_IPV4 => Ipv4Addr::from(read_array::<4>(socket)?).to_string(),
+        ADDRESS_IPV6 => Ipv6Addr::from(read_array::<16>(socket)?).to_string(),
+        ADDRESS_DOMAIN => {
+            let [len] = read_array(socket)?;
+            let mut name = vec![0; len as usize];
+            socket.read_exact(&mut name)?;
+            String::from_utf8(name).map_err(|_| Error::new("Invalid SOCKS host name"))?
+        }
+        _ => {
+            reply(socket, ADDRESS_NOT_SUPPORTED)?;
+            return Err(Error::new(format!(
+                "Unsupported SOCKS address type {address_type}"
+            )));
+        }
+    };
+    let port = u16::from_be_bytes(read_array(socket)?);
+    Ok((host, port))
+}
+
+/// Answers the request. The bound address is all zeros, as the device's end
+/// of the tunnel has no address worth telling.
+fn reply(socket: &mut TcpStream, code: u8) -> Result<(), Error> {
+    socket.write_all(&[VERSION, code, 0, ADDRESS_IPV4, 0, 0, 0, 0, 0, 0])?;
+    Ok(())
+}
+
+fn read_array<const N: usize>(socket: &mut TcpStream) -> Result<[u8; N], Error> {
+    let mut buf = [0; N];
+    socket.read_exact(&mut buf)?;
+    Ok(buf)
+}
@@ -0,0 +1,180 @@
+//! Directory listing over an exec channel, for devices without SFTP. Only
+//! `stat -c`, `readlink` and a POSIX shell are needed, which busybox has.
+
+use crate::byte_string::Encoding;
+use crate::command_line::quote;
+use crate::conn_pool::{DeviceConnection, DeviceConnectionUserInfo, ExecuteCommand};
+use crate::error::Error;
+use crate::remote_files::{FileItem, LinkInfo, PermInfo};
+
+/// Prints the stat line, the name, the link target, empty for anything but
+/// a link, and whether what the name points to exists, of each entry. Each is
+/// NUL-terminated, so any name comes through. The globs match hidden files but
+/// not `.` and `..`.
+const LS_SCRIPT: &str = r#"
+for f in .[!.]* ..?* *; do
+  [ -e "$f" ] && e=1 || e=0
+  [ $e = 1 ] || [ -L "$f" ] || continue
+  t=
+  [ -L "$f" ] && t=$(readlink -- "$f")
+  printf '%s\0%s\0%s\0%s\0' "$(stat -c '%f %s %Y %u %g %U %G' -- "$f")" "$f" "$t" "$e"
+done
+"#;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Lists `path` like `sftp.read_dir` would, with `lstat` semantics.
+pub(crate) fn read_dir(session: &DeviceConnection, path: &str) -> Result<Vec<FileItem>, Error> {
+    let command = format!("cd -- {} && {LS_SCRIPT}", quote(path));
+    let output = session.execute_command(&command, None, Encoding::Binary)?;
+    let fields: Vec<String> = output
+        .stdout
+        .as_ref()
+        .split(|b| *b == 0)
+        .map(|field| String::from_utf8_lossy(field).into_owned())
+        .collect();
+    let user = session.user.as_ref();
+    Ok(fields
+        .chunks_exact(4)
+        .filter_map(|entry| {
+            let link = LinkInfo {
+                target: Some(entry[2].clone()).filter(|target| !target.is_empty()),
+                broken: Some(entry[3] != "1"),
+            };
+            let item = FileItem::parse_stat(&entry[0], &entry[1], link, user);
+            if item.is_none() {
+                log::warn!("Unexpected stat output for {}: {}", entry[1], entry[0]);
+            }
+            item
+        })
+        .collect())
+}
+
+impl FileItem {
+    /// Parses one line of `stat -c '%f %s %Y %u %g %U %G'`. The `link` is only
+    /// kept for a link.
+    fn parse_stat(
+        line: &str,
+        filename: &str,
+        link: LinkInfo,
+        user: Option<&DeviceConnectionUserInfo>,
+    ) -> Option<FileItem> {
+        let mut fields = line.split(' ');
+        let mode = u32::from_str_radix(fields.next()?, 16).ok()?;
+        let size = fields.next()?.parse::<usize>().ok()?;
+        let mtime = fields.next()?.parse::<f64>().ok()?;
+        let uid = fields.next()?.parse::<u32>().ok()?;
+        let gid = fields.next()?.parse::<u32>().ok()?;
+        // busybox prints UNKNOWN for ids without a name.
+        let name = |s: Option<&str>| s.filter(|s| *s != "UNKNOWN").map(String::from);
+        let owner = name(fields.next());
+        let group = name(fields.next());
+        Some(FileItem {
+            filename: filename.to_string(),
+            r#type: String::from(abbrev_mode(mode)),
+            mode: unix_mode::to_string(mode),
+            user: owner,
+            group,
+            size,
+            mtime,
+            link: ((mode & S_IFMT) == S_IFLNK).then_some(link),
+            access: user.map(|u| PermInfo::new(mode, uid, gid, u)),
+        })
+    }
+}
+
+/// The type letter `sftp.rs` gives the same file.
+fn abbrev_mode(mode: u32) -> char {
+    match mode & S_IFMT {
+        S_IFDIR => 'd',
+        S_IFREG => '-',
+        S_IFLNK => 'l',
+        0 => ' ',
+        _ => 'b',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::byte_string::Encoding;
+    use crate::conn_pool::{DeviceConnection, ExecuteCommand};
+    use crate::remote_files::exec::read_dir;
+    use crate::remote_files::{FileItem, LinkInfo};
+    use crate::tests::common::{test_device, SshContainer, TempDir};
+
+    fn no_link() -> LinkInfo {
+        LinkInfo {
+            target: None,
+            broken: Some(false),
+        }
+    }
+
+    #[test]
+    fn parse_stat() {
+        let item = FileItem::parse_stat(
+            "81a4 1234 1700000000 0 0 root UNKNOWN",
+            "a b",
+            no_link(),
+            None,
+        )
+        .expect("Failed to parse");
+        assert_eq!("a b", item.filename);
+        assert_eq!("-", item.r#type);
+        assert_eq!("-rw-r--r--", item.mode);
+        assert_eq!(Some("root"), item.user.as_deref());
+        assert_eq!(None, item.group);
+        assert_eq!(1234, item.size);
+        assert!(item.link.is_none());
+    }
+
+    #[test]
+    fn parse_stat_link() {
+        let link = LinkInfo {
+            target: Some(String::from("/nowhere")),
+            broken: Some(true),
+        };
+        let item = FileItem::parse_stat("a1ff 8 1700000000 0 0 root root", "gone", link, None)
+            .expect("Failed to parse");
+        assert_eq!("l", item.r#type);
+        let link = item.link.expect("No link info");
+        assert_eq!(Some("/nowhere"), link.target.as_deref());
+        assert_eq!(Some(true), link.broken);
+    }
+
+    #[test]
+    fn read_dir_sshd() {
+        let sshd = SshContainer::new();
+        let port = sshd.wait();
+        let device = test_device(port);
+        let conf_dir = TempDir::new();
+        let conn = DeviceConnection::new(device, None, Some(conf_dir.path()))
+            .expect("Failed to create connection");
+        let items = read_dir(&conn, "/etc").expect("Failed to list");
+        let passwd = items
+            .iter()
+            .find(|item| item.filename == "passwd")
+            .expect("No passwd in /etc");
+        assert_eq!("-", passwd.r#type);
+        assert!(passwd.access.as_ref().is_some_and(|access| access.read));
+
+        conn.execute_command(
+            "mkdir -p /tmp/links && cd /tmp/links && ln -sf /etc/passwd pw && ln -sf /nowhere gone",
+            None,
+            Encoding::String,
+        )
+        .expect("Failed to make links");
+        let items = read_dir(&conn, "/tmp/links").expect("Failed to list");
+        let link = |name: &str| {
+            let item = items.iter().find(|item| item.filename == name).unwrap();
+            assert_eq!("l", item.r#type);
+            item.link.clone().expect("No link info")
+        };
+        let pw = link("pw");
+        assert_eq!(Some("/etc/passwd"), pw.target.as_deref());
+        assert_eq!(Some(false), pw.broken);
+        assert_eq!(Some(true), link("gone").broken);
+    }
+}
@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 
+pub(crate) mod exec;
 pub(crate) mod serve;
 mod sftp;
 
@@ -37,8 +37,17 @@ impl FileItem {
 
 impl PermInfo {
     pub fn from(stat: &Metadata, user: &DeviceConnectionUserInfo) -> Self {
-        let perms = stat.permissions().unwrap_or(0);
-        if user.uid.id == stat.uid().unwrap_or(0) {
+        PermInfo::new(
+            stat.permissions().unwrap_or(0),
+            stat.uid().unwrap_or(0),
+            stat.gid().unwrap_or(0),
+            user,
+        )
+    }
+
+    /// What `user` may do with a file of mode `perms`, owned by `uid:gid`.
+    pub(crate) fn new(perms: u32, uid: u32, gid: u32, user: &DeviceConnectionUserInfo) -> Self {
+        if user.uid.id == uid {
             return PermInfo {
                 read: (perms & 0o400) != 0,
                 write: (perms & 0o200) != 0,
@@ -46,7 +55,7 @@ impl PermInfo {
             };
         }
         for group in &user.groups {
-            if group.id == stat.gid().unwrap_or(0) {
+            if group.id == gid {
                 return PermInfo {
                     read: (perms & 0o040) != 0,
                     write: (perms & 0o020) != 0,
@@ -54,7 +63,7 @@ impl PermInfo {
                 };
             }
         }
-        if user.gid.id == stat.gid().unwrap_or(0) {
+        if user.gid.id == gid {
             return PermInfo {
                 read: (perms & 0o040) != 0,
                 write: (perms & 0o020) != 0,
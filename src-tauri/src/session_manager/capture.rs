@@ -0,0 +1,248 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Instant, SystemTime};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use uuid::Uuid;
+
+use crate::error::Error;
+use crate::session_manager::{Capture, CaptureInfo, CaptureOptions, Proc};
+
+const DEFAULT_MAX_FILES: u32 = 5;
+
+/// A line without a newline is written out once it gets this long, so a
+/// stream that never ends a line can't grow without bound.
+const MAX_LINE: usize = 64 * 1024;
+
+impl Capture {
+    /// Opens the file named in `options` inside `dir` for appending, so
+    /// restarting a capture keeps what the last one wrote. The name comes
+    /// from the webview, so one that points anywhere else is refused.
+    pub fn open(options: CaptureOptions, dir: &Path) -> Result<Capture, Error> {
+        let mut components = Path::new(&options.name).components();
+        let (Some(Component::Normal(_)), None) = (components.next(), components.next()) else {
+            return Err(Error::new(format!(
+                "Bad capture file name {}",
+                options.name
+            )));
+        };
+        let path = dir.join(&options.name);
+        let file = Self::open_file(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Capture {
+            id: Uuid::new_v4(),
+            options,
+            path,
+            file,
+            size,
+            written: 0,
+            partial: Default::default(),
+            started_at: Instant::now(),
+        })
+    }
+
+    pub(crate) fn write(&mut self, fd: u32, data: &[u8]) -> io::Result<()> {
+        if !self.options.timestamps && !self.options.tag_streams {
+            return self.write_raw(data);
+        }
+        let mut partial = std::mem::take(&mut self.partial[fd as usize]);
+        partial.extend_from_slice(data);
+        let mut start = 0;
+        while let Some(end) = partial[start..].iter().position(|b| *b == b'\n') {
+            self.write_line(fd, &partial[start..start + end + 1])?;
+            start += end + 1;
+        }
+        partial.drain(..start);
+        if partial.len() >= MAX_LINE {
+            partial.push(b'\n');
+            self.write_line(fd, &partial)?;
+            partial.clear();
+        }
+        self.partial[fd as usize] = partial;
+        Ok(())
+    }
+
+    /// Writes out the unfinished lines, for when the command ended.
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        for fd in 0..2 {
+            let mut partial = std::mem::take(&mut self.partial[fd as usize]);
+            if !partial.is_empty() {
+                partial.push(b'\n');
+                self.write_line(fd, &partial)?;
+            }
+        }
+        self.file.flush()
+    }
+
+    fn info(&self, proc: &Proc) -> CaptureInfo {
+        CaptureInfo {
+            id: self.id.to_string(),
+            device: proc.device.name.clone(),
+            command: proc.command.clone(),
+            path: self.path.clone(),
+            bytes_written: self.written,
+            age: self.started_at.elapsed().as_secs_f64(),
+        }
+    }
+
+    fn write_line(&mut self, fd: u32, line: &[u8]) -> io::Result<()> {
+        let mut prefixed = Vec::with_capacity(line.len() + 32);
+        if self.options.timestamps {
+            prefixed.extend_from_slice(format_utc(SystemTime::now()).as_bytes());
+            prefixed.push(b' ');
+        }
+        if self.options.tag_streams {
+            prefixed.extend_from_slice(if fd == 0 { b"out " } else { b"err " });
+        }
+        prefixed.extend_from_slice(line);
+        self.write_raw(&prefixed)
+    }
+
+    /// Writes to the current file, rotating first if `data` would take it
+    /// past the size limit. A file is never left empty, so a single write
+    /// larger than the limit still goes in.
+    fn write_raw(&mut self, data: &[u8]) -> io::Result<()> {
+        let over = self
+            .options
+            .max_size
+            .is_some_and(|max_size| self.size > 0 && self.size + data.len() as u64 > max_size);
+        if over {
+            self.rotate()?;
+        }
+        self.file.write_all(data)?;
+        self.size += data.len() as u64;
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    /// Moves `path.n` to `path.n+1` for each kept file, dropping the oldest,
+    /// then `path` to `path.1`, and starts a new `path`.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let max_files = self.options.max_files.unwrap_or(DEFAULT_MAX_FILES);
+        if max_files == 0 {
+            self.file.set_len(0)?;
+            self.size = 0;
+            return Ok(());
+        }
+        let path = &self.path;
+        fs::remove_file(rotated(path, max_files)).or_else(ignore_not_found)?;
+        for n in (1..max_files).rev() {
+            fs::rename(rotated(path, n), rotated(path, n + 1)).or_else(ignore_not_found)?;
+        }
+        fs::rename(path, rotated(path, 1))?;
+        self.file = Self::open_file(path)?;
+        self.size = 0;
+        log::info!("Rotated capture {}", path.display());
+        Ok(())
+    }
+
+    fn open_file(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+}
+
+impl Proc {
+    pub fn is_capturing(&self) -> bool {
+        self.capture.lock().unwrap().is_some()
+    }
+
+    pub fn capture_info(&self) -> Option<CaptureInfo> {
+        self.capture.lock().unwrap().as_ref().map(|c| c.info(self))
+    }
+}
+
+fn rotated(path: &Path, n: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+fn ignore_not_found(e: io::Error) -> io::Result<()> {
+    if e.kind() == io::ErrorKind::NotFound {
+        return Ok(());
+    }
+    Err(e)
+}
+
+/// Formats `time` like `2024-05-01T12:34:56.789Z`.
+fn format_utc(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::session_manager::capture::{format_utc, rotated};
+    use crate::session_manager::{Capture, CaptureOptions};
+    use crate::tests::common::TempDir;
+
+    fn options() -> CaptureOptions {
+        CaptureOptions {
+            name: String::from("output.log"),
+            max_size: None,
+            max_files: None,
+            timestamps: false,
+            tag_streams: false,
+        }
+    }
+
+    #[test]
+    fn format_utc_date() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_709_210_096_789);
+        assert_eq!("2024-02-29T12:34:56.789Z", format_utc(time));
+    }
+
+    #[test]
+    fn tags_whole_lines() {
+        let dir = TempDir::new();
+        let options = CaptureOptions {
+            tag_streams: true,
+            ..options()
+        };
+        let path = dir.path().join("output.log");
+        let mut capture = Capture::open(options, dir.path()).unwrap();
+        capture.write(0, b"hel").unwrap();
+        capture.write(1, b"oops\n").unwrap();
+        capture.write(0, b"lo\nbye").unwrap();
+        capture.finish().unwrap();
+        assert_eq!(
+            "err oops\nout hello\nout bye\n",
+            fs::read_to_string(path).unwrap()
+        );
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let dir = TempDir::new();
+        let options = CaptureOptions {
+            max_size: Some(8),
+            max_files: Some(2),
+            ..options()
+        };
+        let path = dir.path().join("output.log");
+        let mut capture = Capture::open(options, dir.path()).unwrap();
+        for chunk in ["aaaaaa", "bbbbbb", "cccccc", "dddddd"] {
+            capture.write(0, chunk.as_bytes()).unwrap();
+        }
+        assert_eq!("dddddd", fs::read_to_string(&path).unwrap());
+        assert_eq!("cccccc", fs::read_to_string(rotated(&path, 1)).unwrap());
+        assert_eq!("bbbbbb", fs::read_to_string(rotated(&path, 2)).unwrap());
+        assert!(!rotated(&path, 3).exists());
+    }
+
+    #[test]
+    fn stays_in_dir() {
+        let dir = TempDir::new();
+        for name in ["../output.log", "/tmp/output.log", "logs/output.log", ""] {
+            let options = CaptureOptions {
+                name: String::from(name),
+                ..options()
+            };
+            assert!(Capture::open(options, dir.path()).is_err(), "{name}");
+        }
+    }
+}
@@ -1,10 +1,11 @@
 use std::path::Path;
 use std::sync::{Arc, Condvar, Mutex};
 
-use crate::conn_pool::{DeviceConnectionPool, ManagedDeviceConnection};
+use crate::conn_pool::{DeviceConnectionPool, DeviceConnectionPoolStatus, ManagedDeviceConnection};
 use crate::device_manager::Device;
 use crate::error::Error;
-use crate::session_manager::{Proc, SessionManager};
+use crate::output_frames::OutputFlow;
+use crate::session_manager::{Capture, Proc, ProcPty, SessionManager};
 
 impl SessionManager {
     pub fn session(&self, device: Device) -> Result<ManagedDeviceConnection, Error> {
@@ -31,20 +32,66 @@ impl SessionManager {
         }
     }
 
-    pub fn spawn(&self, device: Device, command: &str) -> Proc {
+    pub fn spawn(
+        &self,
+        device: Device,
+        command: &str,
+        pty: Option<ProcPty>,
+        capture: Option<Capture>,
+    ) -> Proc {
         Proc {
             device,
             command: String::from(command),
+            pty,
             callback: Mutex::default(),
             ready: Arc::new((Mutex::default(), Condvar::new())),
             sender: Mutex::default(),
             interrupted: Mutex::new(false),
+            flow: OutputFlow::default(),
+            capture: Mutex::new(capture),
         }
     }
 
-    fn pool(&self, device: Device) -> DeviceConnectionPool {
+    /// What each cached pool holds, sorted by device name.
+    pub fn pool_status(&self) -> Vec<DeviceConnectionPoolStatus> {
+        let mut status: Vec<DeviceConnectionPoolStatus> = self
+            .pools
+            .lock()
+            .expect("Failed to lock SessionManager::pools")
+            .iter()
+            .map(|(name, pool)| pool.status(name))
+            .collect();
+        status.sort_by(|a, b| a.device.cmp(&b.device));
+        status
+    }
+
+    /// Forgets the pool of a device, so the next session logs in afresh.
+    /// Sessions already handed out stay usable until they are returned.
+    pub fn drop_pool(&self, name: &str) {
+        if self
+            .pools
+            .lock()
+            .expect("Failed to lock SessionManager::pools")
+            .remove(name)
+            .is_some()
+        {
+            log::info!("Dropped connection pool of {name}");
+        }
+    }
+
+    /// The pool of a device, if anything has used one since it was last
+    /// dropped. Unlike [`pool`](Self::pool), never makes one.
+    pub fn cached_pool(&self, name: &str) -> Option<DeviceConnectionPool> {
+        self.pools
+            .lock()
+            .expect("Failed to lock SessionManager::pools")
+            .get(name)
+            .cloned()
+    }
+
+    pub fn pool(&self, device: Device) -> DeviceConnectionPool {
         if device.new {
-            return DeviceConnectionPool::new(device, self.ssh_dir.get().map(Path::to_path_buf));
+            return self.new_pool(device);
         }
         if let Some(p) = self
             .pools
@@ -55,11 +102,19 @@ impl SessionManager {
             return p.clone();
         }
         let key = device.name.clone();
-        let pool = DeviceConnectionPool::new(device, self.ssh_dir.get().map(Path::to_path_buf));
+        let pool = self.new_pool(device);
         self.pools
             .lock()
             .expect("Failed to lock SessionManager::pools")
             .insert(key, pool.clone());
         pool
     }
+
+    fn new_pool(&self, device: Device) -> DeviceConnectionPool {
+        DeviceConnectionPool::new(
+            device,
+            self.ssh_dir.get().map(Path::to_path_buf),
+            self.conf_dir.get().map(Path::to_path_buf),
+        )
+    }
 }
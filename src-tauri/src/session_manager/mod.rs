@@ -1,19 +1,26 @@
 use std::collections::HashMap;
+use std::fs::File;
+use std::path::PathBuf;
 use std::sync::mpsc::Sender;
 use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 use crate::app_dirs::DirSlot;
 use crate::conn_pool::DeviceConnectionPool;
 use crate::device_manager::Device;
+use crate::output_frames::OutputFlow;
 
+mod capture;
 mod manager;
 mod proc;
 
 #[derive(Default)]
 pub struct SessionManager {
     pub ssh_dir: DirSlot,
+    pub conf_dir: DirSlot,
     pools: Mutex<HashMap<String, DeviceConnectionPool>>,
 }
 
@@ -22,14 +29,104 @@ pub struct Proc {
     pub(crate) command: String,
     pub(crate) callback: Mutex<Option<Box<dyn ProcCallback + Send>>>,
     pub(crate) ready: Arc<(Mutex<bool>, Condvar)>,
-    pub(crate) sender: Mutex<Option<Sender<Vec<u8>>>>,
+    pub(crate) pty: Option<ProcPty>,
+    pub(crate) sender: Mutex<Option<Sender<ProcInput>>>,
     pub(crate) interrupted: Mutex<bool>,
+    pub(crate) flow: OutputFlow,
+    pub(crate) capture: Mutex<Option<Capture>>,
 }
 
-#[derive(Clone, Serialize)]
-pub struct ProcData {
-    pub fd: u32,
-    pub data: Vec<u8>,
+/// A terminal for the command, for programs that act differently without
+/// one. Its output all comes on stdout then.
+#[derive(Deserialize, Clone, Debug)]
+pub struct ProcPty {
+    /// `TERM` of the terminal, `xterm` if not given.
+    pub term: Option<String>,
+    pub rows: u16,
+    pub cols: u16,
+}
+
+/// Where and how to keep a copy of a command's output.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureOptions {
+    /// Name of the file the output is appended to, in the app's capture
+    /// directory.
+    pub name: String,
+    /// Size in bytes past which the file is moved to `path.1`, and older
+    /// ones up by one. Never rotated if not given.
+    pub max_size: Option<u64>,
+    /// How many rotated files to keep, 5 if not given.
+    pub max_files: Option<u32>,
+    /// Starts each line with the time it was read, in UTC.
+    #[serde(default)]
+    pub timestamps: bool,
+    /// Starts each line with `out` or `err` for the stream it came on.
+    #[serde(default)]
+    pub tag_streams: bool,
+}
+
+/// A copy of a command's output being written to a local file.
+pub struct Capture {
+    id: Uuid,
+    options: CaptureOptions,
+    path: PathBuf,
+    file: File,
+    /// Size of the current file.
+    size: u64,
+    /// Bytes written over all files.
+    written: u64,
+    /// The unfinished last line of each stream, held back while lines are
+    /// prefixed so streams don't mix within a line.
+    partial: [Vec<u8>; 2],
+    started_at: Instant,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureInfo {
+    pub id: String,
+    pub device: String,
+    pub command: String,
+    pub path: PathBuf,
+    pub bytes_written: u64,
+    /// Seconds since the capture started.
+    pub age: f64,
+}
+
+/// What the client sends to a running command.
+#[derive(Debug)]
+pub(crate) enum ProcInput {
+    Data(Vec<u8>),
+    /// Closes stdin. Later data is dropped.
+    Eof,
+    /// Only does something for a command with a [`ProcPty`].
+    Resize { rows: u16, cols: u16 },
+    /// Sends `signal`, then `KILL` if the command is still running after
+    /// `kill_after`.
+    Signal {
+        signal: ProcSignal,
+        kill_after: Option<Duration>,
+    },
+}
+
+/// The signals SSH can deliver, by their names without `SIG`.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ProcSignal {
+    Abrt,
+    Alrm,
+    Fpe,
+    Hup,
+    Ill,
+    Int,
+    Kill,
+    Pipe,
+    Quit,
+    Segv,
+    Term,
+    Usr1,
+    Usr2,
 }
 
 #[derive(Debug, Clone, Serialize, PartialEq)]
@@ -42,6 +139,8 @@ pub enum ProcResult {
         signal: Option<String>,
         core_dumped: bool,
     },
+    /// The channel closed without the server saying how the command ended,
+    /// as when it was closed on a command that outlived `KILL`.
     Closed,
 }
 
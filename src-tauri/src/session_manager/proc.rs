@@ -2,19 +2,27 @@ use std::fmt::{Debug, Formatter};
 use std::io::Write;
 use std::sync::mpsc::{channel, RecvTimeoutError};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use libssh_rs::Channel;
 
 use crate::conn_pool::ManagedDeviceConnection;
 use crate::error::Error;
-use crate::session_manager::{Proc, ProcResult, SessionManager};
+use crate::output_frames::{Frame, OutputFrames};
+use crate::session_manager::{Proc, ProcInput, ProcResult, ProcSignal, SessionManager};
 
 /// How long the loop parks waiting for stdin before polling the remote for
 /// output again. Small enough to feel instant, large enough to keep a
 /// long-running command off the CPU.
 const POLL_INTERVAL: Duration = Duration::from_millis(10);
 
+/// How long an interrupted command has to exit on `TERM` before `KILL`.
+const INTERRUPT_KILL_AFTER: Duration = Duration::from_secs(3);
+
+/// How long a command has to exit on `KILL` before its channel is closed
+/// anyway, for servers that ignore signal requests.
+const KILL_CLOSE_AFTER: Duration = Duration::from_secs(2);
+
 impl Proc {
     pub fn is_ready(&self) -> bool {
         let (lock, _cvar) = &*self.ready;
@@ -37,6 +45,7 @@ impl Proc {
         Ok(())
     }
 
+    /// Closes stdin and sends `TERM`, then `KILL` if the command doesn't exit.
     pub fn interrupt(&self) {
         *self.interrupted.lock().unwrap() = true;
     }
@@ -50,8 +59,24 @@ impl Proc {
     }
 
     pub fn write(&self, data: Vec<u8>) -> Result<(), Error> {
+        self.send(ProcInput::Data(data))
+    }
+
+    pub fn close_stdin(&self) -> Result<(), Error> {
+        self.send(ProcInput::Eof)
+    }
+
+    pub fn resize(&self, rows: u16, cols: u16) -> Result<(), Error> {
+        self.send(ProcInput::Resize { rows, cols })
+    }
+
+    pub fn signal(&self, signal: ProcSignal, kill_after: Option<Duration>) -> Result<(), Error> {
+        self.send(ProcInput::Signal { signal, kill_after })
+    }
+
+    fn send(&self, input: ProcInput) -> Result<(), Error> {
         if let Some(sender) = self.sender.lock().unwrap().as_ref() {
-            if let Ok(_) = sender.send(data) {
+            if let Ok(_) = sender.send(input) {
                 return Ok(());
             }
             return Ok(());
@@ -61,7 +86,7 @@ impl Proc {
 
     pub fn wait_close(&self, sessions: &SessionManager) -> Result<ProcResult, Error> {
         let session: ManagedDeviceConnection;
-        let (sender, receiver) = channel::<Vec<u8>>();
+        let (sender, receiver) = channel::<ProcInput>();
         *self.sender.lock().unwrap() = Some(sender);
         let channel: Channel;
         loop {
@@ -81,39 +106,54 @@ impl Proc {
                 Err(e) => return Err(e),
             };
         }
+        if let Some(pty) = &self.pty {
+            let term = pty.term.as_deref().unwrap_or("xterm");
+            channel.request_pty(term, pty.cols as u32, pty.rows as u32)?;
+        }
         channel.request_exec(&self.command)?;
-        let mut buf = [0; 8192];
-        let mut interrupted = false;
+        // A capture has the whole output, so a client that can't keep up,
+        // or went away, misses frames instead of holding the command back.
+        let mut frames = OutputFrames::new(&self.flow, self.is_capturing());
+        let mut escalation = Escalation::default();
+        let mut stdin_closed = false;
+        let mut abandoned = false;
         while !channel.is_closed() && !channel.is_eof() {
-            if self.interrupted.lock().unwrap().eq(&true) {
-                channel.send_eof()?;
+            if std::mem::take(&mut *self.interrupted.lock().unwrap()) {
                 log::info!("interrupting {}", &self.command);
-                channel.request_send_signal("TERM")?;
+                if !std::mem::replace(&mut stdin_closed, true) {
+                    channel.send_eof()?;
+                }
+                escalation.signal(&channel, ProcSignal::Term, Some(INTERRUPT_KILL_AFTER))?;
+            }
+            if escalation.poll(&channel)? {
+                log::warn!("{self:?} outlived KILL, closing its channel");
                 channel.close()?;
-                interrupted = true;
+                abandoned = true;
                 break;
             }
-            // Forward everything already buffered on both streams before parking,
-            // so a burst of output reaches the client in one pass.
-            for (fd, is_stderr) in [(0, false), (1, true)] {
-                loop {
-                    let buf_size =
-                        match channel.read_timeout(&mut buf, is_stderr, Some(Duration::ZERO)) {
-                            Ok(size) => size,
-                            Err(libssh_rs::Error::TryAgain) => 0,
-                            Err(e) => return Err(Error::from(e)),
-                        };
-                    if buf_size == 0 {
-                        break;
-                    }
-                    self.data(fd, &buf[..buf_size])?;
-                }
-            }
+            // Take everything already buffered on both streams before parking,
+            // so a burst of output reaches the client in one frame.
+            self.read_output(&channel, &mut frames, false)?;
+            self.send_frames(frames.take(false))?;
             // Park until there is stdin to forward or it is time to poll again.
             // The reads above are non-blocking, so this is what keeps the loop
             // from spinning while the command is running.
             match receiver.recv_timeout(POLL_INTERVAL) {
-                Ok(msg) => channel.stdin().write_all(&msg)?,
+                Ok(ProcInput::Data(msg)) if !stdin_closed => channel.stdin().write_all(&msg)?,
+                Ok(ProcInput::Data(_)) => log::debug!("{self:?} dropped data after EOF"),
+                Ok(ProcInput::Eof) if !stdin_closed => {
+                    channel.send_eof()?;
+                    stdin_closed = true;
+                }
+                Ok(ProcInput::Eof) => {}
+                Ok(ProcInput::Resize { rows, cols }) if self.pty.is_some() => {
+                    channel.change_pty_size(cols as u32, rows as u32)?;
+                }
+                Ok(ProcInput::Resize { .. }) => {}
+                Ok(ProcInput::Signal { signal, kill_after }) => {
+                    log::info!("sending {signal:?} to {}", &self.command);
+                    escalation.signal(&channel, signal, kill_after)?;
+                }
                 Err(RecvTimeoutError::Timeout) => {}
                 // The sender lives in `self.sender` for as long as this loop
                 // runs, so this is unreachable in practice; sleep rather than
@@ -121,27 +161,138 @@ impl Proc {
                 Err(RecvTimeoutError::Disconnected) => sleep(POLL_INTERVAL),
             }
         }
-        let mut result = ProcResult::Closed;
-        if interrupted {
-            log::debug!("{self:?} channel interrupted by client");
-            result = ProcResult::Signal {
-                signal: Some(String::from("INT")),
-                core_dumped: false,
-            };
-        } else if let Some(status) = channel.get_exit_status() {
-            log::debug!("{self:?} channel closed with status {status}");
-            result = ProcResult::Exit { status };
+        // Output the client had no room for is still in the channel.
+        if !abandoned {
+            self.read_output(&channel, &mut frames, true)
+                .unwrap_or_else(|e| log::warn!("{self:?} failed to read the rest: {e:?}"));
+        }
+        self.send_frames(frames.take(true))?;
+        if let Some(mut capture) = self.capture.lock().unwrap().take() {
+            capture
+                .finish()
+                .unwrap_or_else(|e| log::warn!("{self:?} failed to finish capture: {e:?}"));
+        }
+        let result = ProcResult::from_channel(&channel);
+        log::debug!("{self:?} channel closed with {result:?}");
+        session.mark_last_ok();
+        Ok(result)
+    }
+
+    /// Moves what the remote sent into `frames`, as much as they have room
+    /// for, or all of it with `all`.
+    fn read_output(
+        &self,
+        channel: &Channel,
+        frames: &mut OutputFrames,
+        all: bool,
+    ) -> Result<(), Error> {
+        let mut buf = [0; 8192];
+        for (fd, is_stderr) in [(0, false), (1, true)] {
+            while all || frames.has_room(fd) {
+                let size = match channel.read_timeout(&mut buf, is_stderr, Some(Duration::ZERO)) {
+                    Ok(size) => size,
+                    Err(libssh_rs::Error::TryAgain) => 0,
+                    Err(e) => return Err(Error::from(e)),
+                };
+                if size == 0 {
+                    break;
+                }
+                frames.push(fd, &buf[..size]);
+                self.capture(fd, &buf[..size]);
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops capturing if the file can't be written, but leaves the command
+    /// running.
+    fn capture(&self, fd: u32, data: &[u8]) {
+        let mut capture = self.capture.lock().unwrap();
+        if let Some(Err(e)) = capture.as_mut().map(|c| c.write(fd, data)) {
+            log::warn!("{self:?} stopped capturing: {e:?}");
+            capture.take();
+        }
+    }
+
+    fn send_frames(&self, frames: Vec<Frame>) -> Result<(), Error> {
+        for frame in frames {
+            self.data(frame.fd, &frame.data)?;
+        }
+        Ok(())
+    }
+}
+
+/// When to follow up on a signal the command may ignore.
+#[derive(Default)]
+struct Escalation {
+    kill_at: Option<Instant>,
+    close_at: Option<Instant>,
+}
+
+impl Escalation {
+    fn signal(
+        &mut self,
+        channel: &Channel,
+        signal: ProcSignal,
+        kill_after: Option<Duration>,
+    ) -> Result<(), Error> {
+        channel.request_send_signal(signal.name())?;
+        if signal == ProcSignal::Kill {
+            self.kill_at = None;
+            self.close_at
+                .get_or_insert(Instant::now() + KILL_CLOSE_AFTER);
+        } else if let Some(kill_after) = kill_after {
+            self.kill_at = Some(Instant::now() + kill_after);
+        }
+        Ok(())
+    }
+
+    /// Sends `KILL` once it is due. Returns whether the channel is to be
+    /// closed because the command outlived it.
+    fn poll(&mut self, channel: &Channel) -> Result<bool, Error> {
+        let now = Instant::now();
+        if self.kill_at.is_some_and(|at| at <= now) {
+            self.signal(channel, ProcSignal::Kill, None)?;
+        }
+        Ok(self.close_at.is_some_and(|at| at <= now))
+    }
+}
+
+impl ProcSignal {
+    /// The name SSH uses for the signal.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            ProcSignal::Abrt => "ABRT",
+            ProcSignal::Alrm => "ALRM",
+            ProcSignal::Fpe => "FPE",
+            ProcSignal::Hup => "HUP",
+            ProcSignal::Ill => "ILL",
+            ProcSignal::Int => "INT",
+            ProcSignal::Kill => "KILL",
+            ProcSignal::Pipe => "PIPE",
+            ProcSignal::Quit => "QUIT",
+            ProcSignal::Segv => "SEGV",
+            ProcSignal::Term => "TERM",
+            ProcSignal::Usr1 => "USR1",
+            ProcSignal::Usr2 => "USR2",
+        }
+    }
+}
+
+impl ProcResult {
+    /// How the command on `channel` ended. Waits for the server to report it,
+    /// so call this once the output has been read.
+    pub(crate) fn from_channel(channel: &Channel) -> ProcResult {
+        if let Some(status) = channel.get_exit_status() {
+            ProcResult::Exit { status }
         } else if let Some(signal) = channel.get_exit_signal() {
-            log::debug!("{self:?} channel closed with signal {signal:?}");
-            result = ProcResult::Signal {
+            ProcResult::Signal {
                 signal: signal.signal_name,
                 core_dumped: signal.core_dumped,
-            };
+            }
         } else {
-            log::debug!("{self:?} channel closed with unknown status");
+            ProcResult::Closed
         }
-        session.mark_last_ok();
-        Ok(result)
     }
 }
 
@@ -153,3 +304,82 @@ impl Debug for Proc {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::error::Error;
+    use crate::session_manager::{Proc, ProcCallback, ProcResult, ProcSignal, SessionManager};
+    use crate::tests::common::{test_device, SshContainer, TempDir};
+
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl ProcCallback for Output {
+        fn rx(&self, _fd: u32, data: &[u8]) {
+            self.0.lock().unwrap().extend_from_slice(data);
+        }
+    }
+
+    /// Runs `proc` on a thread, and calls `input` once it takes input.
+    fn run<F>(sessions: SessionManager, proc: Proc, input: F) -> Result<ProcResult, Error>
+    where
+        F: Fn(&Proc) -> Result<(), Error>,
+    {
+        let proc = Arc::new(proc);
+        let worker = {
+            let proc = proc.clone();
+            thread::spawn(move || proc.wait_close(&sessions))
+        };
+        // The input channel opens with the session.
+        while input(&proc).is_err() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        worker.join().unwrap()
+    }
+
+    #[test]
+    fn signal_escalates_to_kill() {
+        let sshd = SshContainer::new();
+        let sessions = SessionManager::default();
+        let conf_dir = TempDir::new();
+        sessions.conf_dir.set(conf_dir.path().to_path_buf());
+        let proc = sessions.spawn(
+            test_device(sshd.wait()),
+            "trap '' TERM; exec sleep 30",
+            None,
+            None,
+        );
+        let result = run(sessions, proc, |proc| {
+            proc.signal(ProcSignal::Term, Some(Duration::from_secs(1)))
+        })
+        .expect("Failed to run command");
+        assert_eq!(
+            ProcResult::Signal {
+                signal: Some(String::from("KILL")),
+                core_dumped: false,
+            },
+            result
+        );
+    }
+
+    #[test]
+    fn eof_ends_filter() {
+        let sshd = SshContainer::new();
+        let sessions = SessionManager::default();
+        let conf_dir = TempDir::new();
+        sessions.conf_dir.set(conf_dir.path().to_path_buf());
+        let proc = sessions.spawn(test_device(sshd.wait()), "sort", None, None);
+        let output = Arc::new(Mutex::new(Vec::new()));
+        *proc.callback.lock().unwrap() = Some(Box::new(Output(output.clone())));
+        let result = run(sessions, proc, |proc| {
+            proc.write(b"b\na\n".to_vec())?;
+            proc.close_stdin()
+        })
+        .expect("Failed to run command");
+        assert_eq!(ProcResult::Exit { status: 0 }, result);
+        assert_eq!(b"a\nb\n", output.lock().unwrap().as_slice());
+    }
+}
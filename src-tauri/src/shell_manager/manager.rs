@@ -1,14 +1,24 @@
 use std::sync::Arc;
 
+use crate::conn_pool::DeviceConnectionPool;
 use crate::device_manager::Device;
 use crate::error::Error;
 use crate::shell_manager::{Shell, ShellInfo, ShellManager, ShellToken};
 
 impl ShellManager {
-    pub fn open(&self, device: Device, rows: u16, cols: u16, dumb: bool) -> Arc<Shell> {
+    pub fn open(
+        &self,
+        device: Device,
+        pool: DeviceConnectionPool,
+        rows: u16,
+        cols: u16,
+        dumb: bool,
+        reconnect: bool,
+    ) -> Arc<Shell> {
         let shell = Arc::new(Shell::new(
             device,
-            self.ssh_dir.get(),
+            pool,
+            reconnect,
             !dumb,
             rows,
             cols,
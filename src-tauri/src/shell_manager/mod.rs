@@ -1,4 +1,3 @@
-use std::path::PathBuf;
 use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
@@ -7,9 +6,10 @@ use serde::Serialize;
 use uuid::Uuid;
 use vt100::Parser;
 
-use crate::app_dirs::DirSlot;
+use crate::conn_pool::DeviceConnectionPool;
 use crate::device_manager::Device;
 use crate::error::Error;
+use crate::output_frames::OutputFlow;
 use crate::shell_manager::shell::ShellsMap;
 
 pub(crate) mod manager;
@@ -19,19 +19,23 @@ pub(crate) mod token;
 #[derive(Default)]
 pub struct ShellManager {
     pub(crate) shells: Arc<Mutex<ShellsMap>>,
-    pub ssh_dir: DirSlot,
 }
 
 pub struct Shell {
     pub token: ShellToken,
     created_at: Instant,
     device: Device,
-    ssh_dir: Option<PathBuf>,
+    pool: DeviceConnectionPool,
+    /// Whether a dropped connection is retried instead of ending the shell.
+    reconnect: bool,
     pub(crate) has_pty: Mutex<Option<bool>>,
     pub(crate) closed: Mutex<Option<ShellState>>,
+    /// The attempt in progress while the shell waits to reconnect.
+    pub(crate) reconnecting: Mutex<Option<u32>>,
     pub(crate) sender: Mutex<Option<Sender<ShellMessage>>>,
     pub(crate) callback: Mutex<Option<Box<dyn ShellCallback + Send + Sync>>>,
     pub(crate) parser: Mutex<Parser>,
+    pub(crate) flow: OutputFlow,
     pub(crate) shells: Arc<Mutex<ShellsMap>>,
 }
 
@@ -55,13 +59,6 @@ pub struct ShellInfo {
     created_at: Instant,
 }
 
-#[derive(Hash, Clone, Debug, Serialize)]
-pub struct ShellData {
-    pub token: ShellToken,
-    pub fd: u32,
-    pub data: Vec<u8>,
-}
-
 #[derive(Clone, Serialize, Debug)]
 pub struct ShellScreen {
     rows: Option<Vec<Vec<u8>>>,
@@ -80,6 +77,9 @@ pub(crate) enum ShellMessage {
 pub enum ShellState {
     Connecting,
     Connected,
+    Reconnecting {
+        attempt: u32,
+    },
     Exited {
         #[serde(rename = "returnCode")]
         return_code: i32,
@@ -1,19 +1,22 @@
 use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
 use std::io::Write;
-use std::path::Path;
 use std::sync::mpsc::{channel, RecvTimeoutError};
 use std::sync::{Arc, Mutex};
 use std::thread::{sleep, JoinHandle};
 use std::time::{Duration, Instant};
 
+use libssh_rs::Channel;
 use libssh_rs::Error::RequestDenied;
 use vt100::Parser;
 
-use crate::conn_pool::DeviceConnection;
+use crate::conn_pool::DeviceConnectionPool;
 use crate::device_manager::Device;
 use crate::error::Error;
-use crate::shell_manager::{Shell, ShellInfo, ShellMessage, ShellScreen, ShellState, ShellToken};
+use crate::output_frames::{OutputFlow, OutputFrames};
+use crate::shell_manager::{
+    Shell, ShellCallback, ShellInfo, ShellMessage, ShellScreen, ShellState, ShellToken,
+};
 
 pub(crate) type ShellsMap = HashMap<ShellToken, Arc<Shell>>;
 
@@ -22,6 +25,12 @@ pub(crate) type ShellsMap = HashMap<ShellToken, Arc<Shell>>;
 /// shell off the CPU.
 const POLL_INTERVAL: Duration = Duration::from_millis(10);
 
+/// The wait before the first reconnect attempt, doubled for each one after.
+const RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+/// About five minutes of trying, long enough for a TV to wake up.
+const MAX_RECONNECT_ATTEMPTS: u32 = 14;
+
 impl Shell {
     pub fn write(&self, data: &[u8]) -> Result<(), Error> {
         self.queue_message(ShellMessage::Data(Vec::from(data)))
@@ -66,6 +75,16 @@ impl Shell {
         })
     }
 
+    /// Sends the output to `callback` in place of the one before. Frames the
+    /// old one never acknowledged are forgotten, so the new one starts with
+    /// the whole window.
+    pub fn attach(&self, callback: Box<dyn ShellCallback + Send + Sync>) {
+        let mut current = self.callback.lock().unwrap();
+        self.flow.reset();
+        *current = Some(callback);
+        log::info!("{self:?} attached to a new output channel");
+    }
+
     pub fn close(&self) -> Result<(), Error> {
         self.queue_message(ShellMessage::Close)?;
         Ok(())
@@ -74,6 +93,8 @@ impl Shell {
     pub fn info(&self) -> ShellInfo {
         let state: ShellState = if let Some(s) = self.closed.lock().unwrap().as_ref() {
             s.clone()
+        } else if let Some(attempt) = *self.reconnecting.lock().unwrap() {
+            ShellState::Reconnecting { attempt }
         } else if self.sender.lock().unwrap().is_some() {
             ShellState::Connected
         } else {
@@ -90,7 +111,8 @@ impl Shell {
 
     pub(crate) fn new(
         device: Device,
-        ssh_dir: Option<&Path>,
+        pool: DeviceConnectionPool,
+        reconnect: bool,
         wants_pty: bool,
         rows: u16,
         cols: u16,
@@ -100,12 +122,15 @@ impl Shell {
             token: ShellToken::new(),
             created_at: Instant::now(),
             device,
-            ssh_dir: ssh_dir.map(|p| p.to_path_buf()),
+            pool,
+            reconnect,
             has_pty: Mutex::new(if !wants_pty { Some(false) } else { None }),
             closed: Mutex::default(),
+            reconnecting: Mutex::default(),
             sender: Mutex::default(),
             callback: Mutex::new(None),
             parser: Mutex::new(Parser::new(rows, cols, 1000)),
+            flow: OutputFlow::default(),
             shells,
         };
         log::info!("{shell:?} created: rows={rows}, cols={cols}");
@@ -142,9 +167,14 @@ impl Shell {
 
     fn worker(&self) -> Result<i32, Error> {
         let (sender, receiver) = channel::<ShellMessage>();
-        let connection = DeviceConnection::new(self.device.clone(), self.ssh_dir.as_deref())?;
-        let channel = connection.new_channel()?;
-        channel.open_session()?;
+        // Every shell of a device runs on the one shared session, so opening
+        // another tab costs a channel rather than a login.
+        let (connection, channel) = self.pool.open_shared(|connection| {
+            let ch = connection.new_channel()?;
+            ch.open_session()?;
+            Ok(ch)
+        })?;
+        connection.mark_last_ok();
         let (rows, cols) = self.parser.lock().unwrap().screen().size();
         let mut has_pty = false;
         if self.has_pty.lock().unwrap().unwrap_or(true) {
@@ -164,45 +194,25 @@ impl Shell {
         }
         channel.request_shell()?;
         *self.sender.lock().unwrap() = Some(sender);
+        self.reconnecting.lock().unwrap().take();
         if let Some(callback) = self.callback.lock().unwrap().as_ref() {
             callback.info(self.info());
         }
-        let mut buf = [0; 8192];
+        let server_alive_interval = connection.settings.server_alive_interval();
+        let mut frames = OutputFrames::new(&self.flow, false);
         while !channel.is_closed() {
-            // Forward everything the remote has already sent. In a PTY stderr is
-            // folded into stdout, so only the dumb shell reads both streams.
-            loop {
-                let size = match channel.read_timeout(&mut buf, false, Some(Duration::ZERO)) {
-                    Ok(size) => size,
-                    Err(libssh_rs::Error::TryAgain) => 0,
-                    Err(e) => return Err(Error::from(e)),
-                };
-                if size == 0 {
-                    break;
-                }
-                if let Some(callback) = self.callback.lock().unwrap().as_ref() {
-                    callback.rx(0, &buf[..size]);
-                }
-                if self.process(&buf[..size]) {
-                    if let Some(callback) = self.callback.lock().unwrap().as_ref() {
-                        callback.info(self.info());
-                    }
-                }
+            if self.read_output(&channel, &mut frames, has_pty, false)? {
+                connection.mark_alive();
             }
-            if !has_pty {
-                loop {
-                    let size = match channel.read_timeout(&mut buf, true, Some(Duration::ZERO)) {
-                        Ok(size) => size,
-                        Err(libssh_rs::Error::TryAgain) => 0,
-                        Err(e) => return Err(Error::from(e)),
-                    };
-                    if size == 0 {
-                        break;
-                    }
-                    if let Some(callback) = self.callback.lock().unwrap().as_ref() {
-                        callback.rx(1, &buf[..size]);
-                    }
-                }
+            self.send_frames(&mut frames, false);
+            // A quiet shell on a TV that went to sleep would otherwise wait
+            // forever for output that never comes. Output of any shell on the
+            // session counts as an answer, so the probe, which holds up every
+            // shell on the session until the device replies, only runs once
+            // all of them have gone quiet. Other shells use the same session,
+            // so the probe keeps its timeout.
+            if server_alive_interval.is_some_and(|interval| connection.idle_time() >= interval) {
+                connection.probe(None)?;
             }
             // Park until there is something to write or it is time to poll the
             // remote again. Both reads above are non-blocking, so without this
@@ -225,9 +235,100 @@ impl Shell {
                 Err(RecvTimeoutError::Disconnected) => sleep(POLL_INTERVAL),
             }
         }
+        // Output the frontend had no room for is still in the channel.
+        self.read_output(&channel, &mut frames, has_pty, true)
+            .unwrap_or_else(|e| log::warn!("{self:?} failed to read the rest: {e:?}"));
+        self.send_frames(&mut frames, true);
+        // A session that died takes its channels with it, which would
+        // otherwise look like the shell exiting.
+        if !connection.is_connected() {
+            return Err(Error::Disconnected);
+        }
         Ok(channel.get_exit_status().unwrap_or(0))
     }
 
+    /// Moves what the remote sent into `frames`, as much as they have room
+    /// for, or all of it with `all`. Returns whether anything came. In a PTY
+    /// stderr is folded into stdout, so only the dumb shell reads both.
+    fn read_output(
+        &self,
+        channel: &Channel,
+        frames: &mut OutputFrames,
+        has_pty: bool,
+        all: bool,
+    ) -> Result<bool, Error> {
+        let mut buf = [0; 8192];
+        let mut received = false;
+        let streams: &[(u32, bool)] = if has_pty {
+            &[(0, false)]
+        } else {
+            &[(0, false), (1, true)]
+        };
+        for &(fd, is_stderr) in streams {
+            while all || frames.has_room(fd) {
+                let size = match channel.read_timeout(&mut buf, is_stderr, Some(Duration::ZERO)) {
+                    Ok(size) => size,
+                    Err(libssh_rs::Error::TryAgain) => 0,
+                    Err(e) => return Err(Error::from(e)),
+                };
+                if size == 0 {
+                    break;
+                }
+                received = true;
+                frames.push(fd, &buf[..size]);
+                // The screen follows every read, so a title change shows at once.
+                if fd == 0 && self.process(&buf[..size]) {
+                    if let Some(callback) = self.callback.lock().unwrap().as_ref() {
+                        callback.info(self.info());
+                    }
+                }
+            }
+        }
+        Ok(received)
+    }
+
+    fn send_frames(&self, frames: &mut OutputFrames, flush: bool) {
+        // Locked before the frames are counted, so `attach` never resets the
+        // count between counting a frame and sending it.
+        let callback = self.callback.lock().unwrap();
+        let frames = frames.take(flush);
+        if let Some(callback) = callback.as_ref() {
+            for frame in frames {
+                callback.rx(frame.fd, &frame.data);
+            }
+        }
+    }
+
+    /// Runs the worker, and again after a connection drop if the shell asked
+    /// for that. The parser outlives each attempt, so the screen and its
+    /// scrollback survive, and the new PTY gets the size it had.
+    fn run(&self) -> Result<i32, Error> {
+        let mut attempt = 0;
+        loop {
+            let result = self.worker();
+            let Err(e) = &result else {
+                return result;
+            };
+            let transient = matches!(e, Error::Disconnected | Error::Timeout | Error::IO { .. });
+            if !self.reconnect || !transient || attempt >= MAX_RECONNECT_ATTEMPTS {
+                return result;
+            }
+            attempt += 1;
+            log::info!("{self:?} lost its connection with {e:?}, reconnecting ({attempt})");
+            self.sender.lock().unwrap().take();
+            *self.reconnecting.lock().unwrap() = Some(attempt);
+            if let Some(callback) = self.callback.lock().unwrap().as_ref() {
+                callback.info(self.info());
+            }
+            let backoff = RECONNECT_BACKOFF * 2u32.pow((attempt - 1).min(5));
+            sleep(backoff.min(MAX_RECONNECT_BACKOFF));
+            // Closed by the person while it waited.
+            if !self.shells.lock().unwrap().contains_key(&self.token) {
+                return result;
+            }
+        }
+    }
+
     fn closed(&self, result: Result<i32, Error>) -> bool {
         *self.closed.lock().unwrap() = Some(match &result {
             Ok(code) => ShellState::Exited {
@@ -249,7 +350,7 @@ impl Shell {
     pub(crate) fn thread(shell: Arc<Shell>) -> JoinHandle<()> {
         log::info!("Starting thread for {shell:?}");
         std::thread::spawn(move || {
-            let result = shell.worker();
+            let result = shell.run();
             log::info!("{shell:?} worker exited with {result:?}");
             if let Ok(0) = result {
                 if shell.shells.lock().unwrap().remove(&shell.token).is_some() {
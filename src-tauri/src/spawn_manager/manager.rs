@@ -1,4 +1,5 @@
-use crate::session_manager::Proc;
+use crate::error::Error;
+use crate::session_manager::{CaptureInfo, Proc};
 use crate::spawn_manager::SpawnManager;
 use std::sync::Arc;
 
@@ -24,4 +25,43 @@ impl SpawnManager {
             }
         }
     }
+
+    pub fn add_capture(&self, proc: Arc<Proc>) {
+        let mut captures = self
+            .captures
+            .lock()
+            .expect("Failed to lock SpawnManager::captures");
+        captures.retain(|x| x.upgrade().is_some_and(|proc| proc.is_capturing()));
+        captures.push(Arc::downgrade(&proc));
+    }
+
+    /// Commands still capturing, oldest first.
+    pub fn captures(&self) -> Vec<CaptureInfo> {
+        self.capturing()
+            .iter()
+            .filter_map(|proc| proc.capture_info())
+            .collect()
+    }
+
+    pub fn stop_capture(&self, id: &str) -> Result<(), Error> {
+        let proc = self
+            .capturing()
+            .into_iter()
+            .find(|proc| proc.capture_info().is_some_and(|info| info.id == id));
+        let Some(proc) = proc else {
+            return Err(Error::NotFound);
+        };
+        log::debug!("Stopping capture of {proc:?}");
+        proc.interrupt();
+        Ok(())
+    }
+
+    fn capturing(&self) -> Vec<Arc<Proc>> {
+        self.captures
+            .lock()
+            .expect("Failed to lock SpawnManager::captures")
+            .iter()
+            .filter_map(|x| x.upgrade())
+            .collect()
+    }
 }
@@ -6,4 +6,7 @@ mod manager;
 #[derive(Default)]
 pub(crate) struct SpawnManager {
     items: Mutex<Vec<Weak<Proc>>>,
+    /// Commands capturing to a file, which outlive the page that started
+    /// them.
+    captures: Mutex<Vec<Weak<Proc>>>,
 }
@@ -1,3 +1,23 @@
+mod ssh_agent;
+mod temp_dir;
 mod test_server;
 
+pub use ssh_agent::SshAgent;
+pub use temp_dir::TempDir;
 pub use test_server::SshContainer;
+
+use crate::device_manager::Device;
+
+/// The device the sshd container on `port` answers as, logging in as root
+/// with the container's password.
+pub fn test_device(port: u16) -> Device {
+    serde_json::from_value(serde_json::json!({
+        "profile": "ose",
+        "name": "test",
+        "host": "127.0.0.1",
+        "port": port,
+        "username": "root",
+        "password": "alpine",
+    }))
+    .expect("Failed to parse test device")
+}
@@ -0,0 +1,67 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use crate::tests::common::SshContainer;
+
+/// An `ssh-agent` of its own for one test, holding the key the sshd container
+/// accepts.
+pub struct SshAgent {
+    process: Child,
+    dir: PathBuf,
+    pub socket: PathBuf,
+}
+
+impl SshAgent {
+    pub fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!("devman-agent-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("Failed to create agent directory");
+        let socket = dir.join("agent.sock");
+        let process = Command::new("ssh-agent")
+            .arg("-D")
+            .arg("-a")
+            .arg(&socket)
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("Failed to start ssh-agent");
+        let mut retries = 0;
+        while !socket.exists() {
+            if retries >= 50 {
+                panic!("Failed to start ssh-agent");
+            }
+            sleep(Duration::from_millis(100));
+            retries += 1;
+        }
+        // Read from stdin, because ssh-add refuses a key file that others can
+        // read, and checkouts don't keep file modes.
+        let mut add = Command::new("ssh-add")
+            .arg("-")
+            .env("SSH_AUTH_SOCK", &socket)
+            .stdin(Stdio::piped())
+            .spawn()
+            .expect("Failed to run ssh-add");
+        let key = std::fs::read(SshContainer::fixture_path("keys/id_root", false))
+            .expect("Failed to read key fixture");
+        add.stdin.take().unwrap().write_all(&key).unwrap();
+        if !add.wait().unwrap().success() {
+            panic!("Failed to add key to ssh-agent");
+        }
+        Self {
+            process,
+            dir,
+            socket,
+        }
+    }
+}
+
+impl Drop for SshAgent {
+    fn drop(&mut self) {
+        self.process.kill().expect("Failed to stop ssh-agent");
+        self.process.wait().unwrap();
+        std::fs::remove_dir_all(&self.dir).unwrap_or(());
+    }
+}
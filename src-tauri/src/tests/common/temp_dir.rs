@@ -0,0 +1,25 @@
+use std::path::{Path, PathBuf};
+
+use uuid::Uuid;
+
+/// A directory of its own for one test, removed with everything in it when
+/// the test ends.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!("devman-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("Failed to create test directory");
+        Self(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.0).unwrap_or(());
+    }
+}
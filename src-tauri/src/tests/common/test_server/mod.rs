@@ -17,6 +17,7 @@ impl SshContainer {
             .args(["run", "--rm", "-d", "-p", "22"])
             .args(["-e", "SSH_ENABLE_ROOT=true"])
             .args(["-e", "SSH_ENABLE_ROOT_PASSWORD_AUTH=true"])
+            .args(["-e", "TCP_FORWARDING=true"])
             .args([
                 "--health-cmd",
                 "netstat -a | grep ':ssh'",
@@ -5,6 +5,7 @@ import {SemVer} from 'semver';
 import {NgbModal} from '@ng-bootstrap/ng-bootstrap';
 import {MessageDialogComponent} from './shared/components/message-dialog/message-dialog.component';
 import {UpdateDetailsComponent} from './update-details/update-details.component';
+import {AuthPromptComponent} from './auth-prompt/auth-prompt.component';
 import {open} from "@tauri-apps/plugin-shell";
 import {noop} from "rxjs";
 
@@ -34,6 +35,11 @@ export class AppComponent {
       }
     }).catch(noop);
     deviceManager.load();
+    deviceManager.authPrompts$.subscribe(request => {
+      AuthPromptComponent.ask(this.modalService, request)
+        .then(answers => answers ? request.answer(answers) : request.cancel())
+        .catch(noop);
+    });
   }
 
   private async notifyUpdate(info: Release, version: SemVer): Promise<void> {
@@ -22,6 +22,7 @@ import {
     NgbTooltipModule
 } from "@ng-bootstrap/ng-bootstrap";
 import {RemoveDeviceComponent} from './remove-device/remove-device.component';
+import {AuthPromptComponent} from './auth-prompt/auth-prompt.component';
 import {AddDeviceModule} from "./add-device/add-device.module";
 import {NgOptimizedImage} from "@angular/common";
 import {Router} from "@angular/router";
@@ -37,6 +38,7 @@ import {ExternalLinkDirective} from "./shared/directives";
         RenewScriptComponent,
         UpdateDetailsComponent,
         RemoveDeviceComponent,
+        AuthPromptComponent,
     ],
     imports: [
         BrowserModule,
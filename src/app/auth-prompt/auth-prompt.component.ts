@@ -0,0 +1,34 @@
+import {Component, Inject, Injector, ChangeDetectionStrategy} from '@angular/core';
+import {NgbActiveModal, NgbModal} from "@ng-bootstrap/ng-bootstrap";
+import {AuthPromptRequest} from "../core/services";
+
+@Component({
+    selector: 'app-auth-prompt',
+    templateUrl: './auth-prompt.component.html',
+    styleUrls: ['./auth-prompt.component.scss'],
+    changeDetection: ChangeDetectionStrategy.Eager,
+    standalone: false
+})
+export class AuthPromptComponent {
+
+  public answers: string[];
+
+  constructor(@Inject('request') public request: AuthPromptRequest, public modal: NgbActiveModal) {
+    this.answers = request.prompt.prompts.map(() => '');
+  }
+
+  /**
+   * Asks the person the questions of a keyboard-interactive login.
+   *
+   * @return The answers, in the order of the questions, or `null` if the person cancelled
+   */
+  static ask(service: NgbModal, request: AuthPromptRequest): Promise<string[] | null> {
+    return service.open(AuthPromptComponent, {
+      centered: true,
+      backdrop: 'static',
+      injector: Injector.create({
+        providers: [{provide: 'request', useValue: request}]
+      })
+    }).result.catch(() => null);
+  }
+}
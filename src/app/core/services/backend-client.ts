@@ -91,12 +91,15 @@ export type ErrorReason =
     'BadPassphrase' |
     'Disconnected' |
     'ExitStatus' |
+    'HostKeyMismatch' |
     'IO' |
+    'Luna' |
     'Message' |
     'NeedsReconnect' |
     'NegativeReply' |
     'NotFound' |
     'PassphraseRequired' |
+    'PasswordChangeRequired' |
     'Timeout' |
     'Unsupported' |
     'UnsupportedKey';
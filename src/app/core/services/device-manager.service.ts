@@ -1,13 +1,15 @@
 import {Injectable, NgZone} from "@angular/core";
-import {BehaviorSubject, from, Observable, Subject} from "rxjs";
+import {BehaviorSubject, from, noop, Observable, Subject} from "rxjs";
+import {Event, listen} from "@tauri-apps/api/event";
 import {CrashReportEntry, Device, DeviceLike, FileItem, FileSession, NewDevice, StorageInfo} from '../../types';
-import {BackendClient, IOError} from "./backend-client";
+import {BackendClient, BackendErrorBody, IOError} from "./backend-client";
 import {FileSessionImpl} from "./file.session";
 import {HomebrewChannelConfiguration, OsInfo, SystemInfo} from "../../types/luna-apis";
 import {LunaResponseError, RemoteLunaService} from "./remote-luna.service";
 import {RemoteCommandService} from "./remote-command.service";
 import {RemoteFileService} from "./remote-file.service";
 import * as path from "@tauri-apps/api/path";
+import {EventChannel} from "../event-channel";
 
 export type ScreenshotMethod = 'DISPLAY' | 'VIDEO' | 'GRAPHIC';
 
@@ -18,6 +20,8 @@ export class DeviceManagerService extends BackendClient {
 
     private devicesSubject: Subject<Device[] | null>;
     private selectedSubject: Subject<Device | null>;
+    private authPromptSubject = new Subject<AuthPromptRequest>();
+    private statusSubject = new BehaviorSubject<Map<string, DeviceStatus>>(new Map());
 
     constructor(zone: NgZone, private cmd: RemoteCommandService, private file: RemoteFileService,
                 private luna: RemoteLunaService) {
@@ -25,6 +29,9 @@ export class DeviceManagerService extends BackendClient {
         this.devicesSubject = new BehaviorSubject<Device[] | null>(null);
         this.selectedSubject = new BehaviorSubject<Device | null>(null);
         this.on('devicesUpdated', (devices: Device[]) => this.onDevicesUpdated(devices));
+        this.on('authPrompt', (event: AuthPromptEvent) => this.authPromptSubject.next(new AuthPromptRequest(event)));
+        listen('device-status', (e: Event<DeviceStatus>) => zone.run(() => this.onDeviceStatus(e.payload))).then(noop);
+        this.monitorStatus().then(list => list.forEach(status => this.onDeviceStatus(status)));
     }
 
     get devices$(): Observable<Device[] | null> {
@@ -35,6 +42,32 @@ export class DeviceManagerService extends BackendClient {
         return this.selectedSubject.asObservable();
     }
 
+    /**
+     * Whether each device answers, by device name, as the background monitor last saw it.
+     */
+    get status$(): Observable<Map<string, DeviceStatus>> {
+        return this.statusSubject.asObservable();
+    }
+
+    /**
+     * What the device can do, probed when the app first connects to it. `null` if probing failed.
+     */
+    async capabilities(device: DeviceLike): Promise<DeviceCapabilities | null> {
+        return await this.invoke('capabilities', {device});
+    }
+
+    async monitorStatus(): Promise<DeviceStatus[]> {
+        return await this.invoke('monitor_status');
+    }
+
+    /**
+     * Keyboard-interactive login questions, which the app component puts in front of the person. Each one has to be
+     * answered or cancelled, or the login waits for it until it times out.
+     */
+    get authPrompts$(): Observable<AuthPromptRequest> {
+        return this.authPromptSubject.asObservable();
+    }
+
 
     load(): void {
         this.list().then(devices => this.onDevicesUpdated(devices));
@@ -74,6 +107,26 @@ export class DeviceManagerService extends BackendClient {
         return await this.invoke('check_connection', {host});
     }
 
+    async acceptHostKey(name: string): Promise<void> {
+        return await this.invoke('host_key_accept', {name});
+    }
+
+    async readSettings(name: string): Promise<DeviceSettings> {
+        return await this.invoke('settings_read', {name});
+    }
+
+    async writeSettings(name: string, settings: DeviceSettings): Promise<void> {
+        return await this.invoke('settings_write', {name, settings});
+    }
+
+    async listPools(): Promise<ConnectionPoolStatus[]> {
+        return await this.invoke('pool_list');
+    }
+
+    async dropPool(name: string): Promise<void> {
+        return await this.invoke('pool_drop', {name});
+    }
+
     async listCrashReports(device: Device): Promise<CrashReport[]> {
         const dirs = [
             '/tmp/faultmanager/crash/',
@@ -135,7 +188,7 @@ export class DeviceManagerService extends BackendClient {
         if (!mountPoint) {
             mountPoint = '/media/developer';
         }
-        return await this.cmd.exec(device, `df ${mountPoint}`, 'utf-8').then((output) => {
+        return await this.cmd.execArgv(device, {argv: ['df', mountPoint]}, 'utf-8').then((output) => {
             const lines = output.trim().split('\n');
             if (lines.length < 2) {
                 return null;
@@ -189,6 +242,12 @@ export class DeviceManagerService extends BackendClient {
         return new FileSessionImpl(this.cmd, this.file, device);
     }
 
+    private onDeviceStatus(status: DeviceStatus): void {
+        const statuses = new Map(this.statusSubject.value);
+        statuses.set(status.name, status);
+        this.statusSubject.next(statuses);
+    }
+
     private onDevicesUpdated(devices: Device[]) {
         this.devicesSubject.next(devices);
         this.selectedSubject.next(devices.find((device) => device.default) ?? devices[0]);
@@ -271,3 +330,96 @@ export declare interface PrivateKeyInfo {
     sha1: string;
     sha256: string;
 }
+
+export declare interface DeviceCapabilities {
+    sftp: boolean;
+    lunaSend: boolean;
+    lunaSendPub: boolean;
+    userland?: 'Busybox' | 'Coreutils';
+    kernel?: string;
+    webosVersion?: string;
+    homebrewChannel: boolean;
+}
+
+export declare interface DeviceStatus {
+    name: string;
+    online: boolean;
+    /**
+     * Milliseconds the SSH port took to accept a connection, never known for a device behind a jump host
+     */
+    latency?: number;
+    /**
+     * Unix time in milliseconds when the device last answered
+     */
+    lastSeen?: number;
+    /**
+     * Whether the SSH probe passed, for devices that ask for one and have an idle session to probe
+     */
+    ssh?: boolean;
+}
+
+export declare interface AuthPrompt {
+    name: string;
+    instruction: string;
+    prompts: { text: string, echo: boolean }[];
+}
+
+declare interface AuthPromptEvent {
+    token: string;
+    device: string;
+    prompt: AuthPrompt;
+}
+
+export class AuthPromptRequest extends EventChannel<never, null> {
+    readonly device: string;
+    readonly prompt: AuthPrompt;
+
+    constructor(event: AuthPromptEvent) {
+        super(event.token);
+        this.device = event.device;
+        this.prompt = event.prompt;
+    }
+
+    async answer(answers: string[]): Promise<void> {
+        await this.send({answers});
+    }
+
+    async cancel(): Promise<void> {
+        await this.close();
+    }
+
+    onReceive(): void {
+    }
+
+    onClose(): void {
+        this.unlisten().then();
+    }
+}
+
+export declare interface DeviceSettings {
+    maxSessions?: number;
+    idleTimeout?: number;
+    connectTimeout?: number;
+    serverAliveInterval?: number;
+    compression?: boolean;
+    jumpHost?: string;
+    sshAgent?: boolean;
+    sshAgentSocket?: string;
+    monitorSsh?: boolean;
+}
+
+export declare interface ConnectionPoolStatus {
+    device: string;
+    connections: number;
+    idleConnections: number;
+    lastError?: BackendErrorBody;
+    sessions: {
+        id: string;
+        user?: {
+            uid: { id: number, name?: string };
+            gid: { id: number, name?: string };
+            groups: { id: number, name?: string }[];
+        };
+        age: number;
+    }[];
+}
@@ -26,7 +26,10 @@ export class ExecutionError extends Error {
         const data = convertOutput(stderr, 'utf-8');
         const exitCode = e['exit_code'] as number;
         const command = e['command'] as string;
-        return new ExecutionError(`Command \`${command}\` exited with code ${exitCode}`, exitCode, data, command);
+        const signal = e['signal'] as string | null | undefined;
+        const message = signal ? `Command \`${command}\` was killed by signal ${signal}`
+            : `Command \`${command}\` exited with code ${exitCode}`;
+        return new ExecutionError(message, exitCode, data, command);
     }
 }
 
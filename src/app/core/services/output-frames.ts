@@ -0,0 +1,48 @@
+import {Channel} from "@tauri-apps/api/core";
+
+export interface OutputFrame {
+    fd: number;
+    data: Uint8Array;
+}
+
+/**
+ * Frames acknowledged at once. The backend sends up to 8 ahead, so acking at half of that keeps it from waiting.
+ */
+const ACK_EVERY = 4;
+
+/**
+ * Milliseconds a frame may wait for its ack when fewer than {@link ACK_EVERY} come after it
+ */
+const ACK_DELAY = 50;
+
+/**
+ * Output of a command or shell comes in raw frames: the fd in the first byte, then the data.
+ *
+ * @param onFrame Called with each frame
+ * @param ack Called with the number of frames handled so far, which the backend waits for before sending more.
+ * Called every {@link ACK_EVERY} frames, or {@link ACK_DELAY} milliseconds after a frame that is not acked yet.
+ */
+export function outputChannel(onFrame: (frame: OutputFrame) => void,
+                              ack: (frames: number) => void): Channel<ArrayBuffer> {
+    const channel = new Channel<ArrayBuffer>();
+    let received = 0;
+    let acked = 0;
+    let timer: ReturnType<typeof setTimeout> | undefined;
+    const flush = () => {
+        clearTimeout(timer);
+        timer = undefined;
+        acked = received;
+        ack(received);
+    };
+    channel.onmessage = (message: ArrayBuffer) => {
+        const bytes = new Uint8Array(message);
+        onFrame({fd: bytes[0], data: bytes.subarray(1)});
+        received++;
+        if (received - acked >= ACK_EVERY) {
+            flush();
+        } else if (timer === undefined) {
+            timer = setTimeout(flush, ACK_DELAY);
+        }
+    };
+    return channel;
+}
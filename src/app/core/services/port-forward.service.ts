@@ -0,0 +1,87 @@
+import {Injectable, NgZone} from "@angular/core";
+import {BackendClient} from "./backend-client";
+import {DeviceLike} from "../../types";
+
+export interface PortForwardInfo {
+    id: string;
+    device: string;
+    /**
+     * `Local` if connections come in on this machine, `Remote` if they come in on the device, `Dynamic` for a SOCKS5
+     * proxy on this machine
+     */
+    direction: 'Local' | 'Remote' | 'Dynamic';
+    localHost: string;
+    localPort: number;
+    /**
+     * Empty for a SOCKS5 proxy
+     */
+    remoteHost: string;
+    remotePort: number;
+    /**
+     * Connections open through the forward
+     */
+    connections: number;
+    /**
+     * Bytes sent from this machine to the device
+     */
+    bytesSent: number;
+    bytesReceived: number;
+    /**
+     * Seconds since the forward started
+     */
+    age: number;
+}
+
+@Injectable({
+    providedIn: 'root'
+})
+export class PortForwardService extends BackendClient {
+    constructor(zone: NgZone) {
+        super(zone, 'port-forward');
+    }
+
+    /**
+     * Makes a port on the device reachable on 127.0.0.1 of this machine. The forward stops when the device's
+     * connections are dropped, such as after its settings change.
+     *
+     * @param device Device to forward to
+     * @param remotePort Port to reach on the device
+     * @param localPort Port to listen on, any free one if omitted
+     * @param remoteHost Host to reach, as seen from the device
+     */
+    async start(device: DeviceLike, remotePort: number, localPort?: number,
+                remoteHost?: string): Promise<PortForwardInfo> {
+        return this.invoke('start', {device, remotePort, localPort, remoteHost});
+    }
+
+    /**
+     * Makes a port of this machine reachable on 127.0.0.1 of the device, such as a mock backend for an app under test.
+     *
+     * @param device Device to listen on
+     * @param localPort Port to reach on this machine
+     * @param remotePort Port to listen on the device, any free one if omitted
+     * @param localHost Host to reach, as seen from this machine
+     */
+    async startReverse(device: DeviceLike, localPort: number, remotePort?: number,
+                       localHost?: string): Promise<PortForwardInfo> {
+        return this.invoke('start_reverse', {device, localPort, remotePort, localHost});
+    }
+
+    /**
+     * Starts a SOCKS5 proxy on 127.0.0.1 of this machine, which connects to wherever each client asks from the device.
+     *
+     * @param device Device to connect from
+     * @param localPort Port to listen on, any free one if omitted
+     */
+    async startSocks(device: DeviceLike, localPort?: number): Promise<PortForwardInfo> {
+        return this.invoke('start_socks', {device, localPort});
+    }
+
+    async list(): Promise<PortForwardInfo[]> {
+        return this.invoke('list');
+    }
+
+    async stop(id: string): Promise<void> {
+        await this.invoke('stop', {id});
+    }
+}
@@ -1,11 +1,12 @@
 import {Injectable, NgZone} from "@angular/core";
-import {BackendClient, BackendError} from "./backend-client";
+import {BackendClient, BackendError, BackendErrorBody} from "./backend-client";
 import {convertOutput, ExecutionError} from "./execution-error";
 import {DeviceLike} from "../../types";
 import {Buffer} from "buffer";
-import {noop, ReplaySubject} from "rxjs";
+import {noop, Observable, ReplaySubject} from "rxjs";
+import {emit} from "@tauri-apps/api/event";
 import {EventChannel} from "../event-channel";
-import {isNil} from "lodash-es";
+import {OutputFrame, outputChannel} from "./output-frames";
 
 // Re-exported so the many existing `from "./remote-command.service"` imports keep working.
 export {ExecutionError, convertOutput};
@@ -40,15 +41,15 @@ export class RemoteCommandService extends BackendClient {
     public async exec(device: DeviceLike, command: string, outputEncoding: 'utf-8', stdinData?: string | Uint8Array): Promise<string>;
 
     public async exec(device: DeviceLike, command: string, outputEncoding?: 'utf-8', stdinData?: string | Uint8Array,
-                      wantStderr?: true): Promise<ExecOutput<string>>;
+                      wantStderr?: true, limits?: ExecLimits): Promise<ExecOutput<string>>;
 
     public async exec<T = Buffer | string>(device: DeviceLike, command: string, outputEncoding?: 'buffer' | 'utf-8',
-                                           stdinData?: string | Uint8Array, wantStderr: boolean = false):
-        Promise<T | ExecOutput<T>> {
+                                           stdinData?: string | Uint8Array, wantStderr: boolean = false,
+                                           limits?: ExecLimits): Promise<T | ExecOutput<T>> {
         const stdin = typeof stdinData === 'string' ? [...this.encoder.encode(stdinData)] : stdinData;
         try {
             const encoding = RemoteCommandService.byteStringEncoding(outputEncoding);
-            const output: ExecOutput<T> = await this.invoke('exec', {device, command, stdin, encoding});
+            const output: ExecOutput<T> = await this.invoke('exec', {device, command, stdin, encoding, limits});
             return wantStderr ? output : output.stdout;
         } catch (e) {
             if (BackendError.isCompatible(e)) {
@@ -60,13 +61,122 @@ export class RemoteCommandService extends BackendClient {
         }
     }
 
-    public async popen(device: DeviceLike, command: string, outputEncoding?: 'buffer'): Promise<CommandSubject<Buffer>>;
-    public async popen(device: DeviceLike, command: string, outputEncoding: 'utf-8'): Promise<CommandSubject<string>>;
+    /**
+     * Like {@link exec}, but the device builds and quotes the command line, so arguments may contain any character.
+     *
+     * @param device Device to invoke command
+     * @param command Program, arguments, environment and working directory
+     * @param outputEncoding
+     * @param stdinData
+     * @throws ExecutionError If the command doesn't exit with status 0
+     */
+    public async execArgv(device: DeviceLike, command: CommandLine, outputEncoding?: 'buffer',
+                          stdinData?: string | Uint8Array): Promise<Buffer>;
+    public async execArgv(device: DeviceLike, command: CommandLine, outputEncoding: 'utf-8',
+                          stdinData?: string | Uint8Array): Promise<string>;
+
+    public async execArgv<T = Buffer | string>(device: DeviceLike, command: CommandLine,
+                                               outputEncoding?: 'buffer' | 'utf-8',
+                                               stdinData?: string | Uint8Array): Promise<T> {
+        const stdin = typeof stdinData === 'string' ? [...this.encoder.encode(stdinData)] : stdinData;
+        try {
+            const encoding = RemoteCommandService.byteStringEncoding(outputEncoding);
+            const output: ExecOutput<T> = await this.invoke('exec_argv', {device, command, stdin, encoding});
+            return output.stdout;
+        } catch (e) {
+            if (BackendError.isCompatible(e)) {
+                if (e.reason === 'ExitStatus') {
+                    throw ExecutionError.fromBackendError(e);
+                }
+            }
+            throw e;
+        }
+    }
+
+    /**
+     * Runs the same command on several devices in parallel. Each device's result is emitted as soon as it finishes,
+     * and the observable completes once all devices are done. Unsubscribing stops devices that haven't started yet.
+     *
+     * @param devices Devices to invoke command on
+     * @param command Command to execute
+     * @param outputEncoding
+     * @param stdinData
+     * @param limits Limits for the command on each device
+     * @param concurrency How many devices to run on at once, 8 by default
+     */
+    public execMany<T = Buffer | string>(devices: DeviceLike[], command: string, outputEncoding?: 'buffer' | 'utf-8',
+                                         stdinData?: string | Uint8Array, limits?: ExecLimits,
+                                         concurrency?: number): Observable<ExecManyResult<T>> {
+        const stdin = typeof stdinData === 'string' ? [...this.encoder.encode(stdinData)] : stdinData;
+        const encoding = RemoteCommandService.byteStringEncoding(outputEncoding);
+        return new Observable<ExecManyResult<T>>(subscriber => {
+            let channel: EventChannel<ExecManyResult<T>, void> | undefined;
+            const zone = this.zone;
+            this.invoke<string>('exec_many', {devices, command, stdin, encoding, limits, concurrency})
+                .then(token => {
+                    if (subscriber.closed) {
+                        // Cancels the run before it starts
+                        emit(`${token}:close`).catch(noop);
+                        return;
+                    }
+                    channel = new class extends EventChannel<ExecManyResult<T>, void> {
+                        constructor() {
+                            super(token);
+                        }
+
+                        onReceive(payload: ExecManyResult<T>): void {
+                            zone.run(() => subscriber.next(payload));
+                        }
 
-    public async popen<T = Buffer | string>(device: DeviceLike, command: string, outputEncoding?: 'buffer' | 'utf-8'):
-        Promise<CommandSubject<T>> {
-        const token: string = await this.invoke('spawn', {device, command});
-        return new CommandSubject<T>(this.zone, token, command, outputEncoding ?? 'buffer');
+                        onClose(): void {
+                            zone.run(() => subscriber.complete());
+                            this.unlisten().catch(noop);
+                        }
+                    }();
+                    channel.send().catch(noop);
+                })
+                .catch(e => subscriber.error(e));
+            return () => {
+                if (channel && !channel.closed) {
+                    channel.close().catch(noop);
+                    channel.unlisten().catch(noop);
+                }
+            };
+        });
+    }
+
+    public async popen(device: DeviceLike, command: string, outputEncoding?: 'buffer',
+                       pty?: ProcPty, capture?: CaptureOptions): Promise<CommandSubject<Buffer>>;
+    public async popen(device: DeviceLike, command: string, outputEncoding: 'utf-8',
+                       pty?: ProcPty, capture?: CaptureOptions): Promise<CommandSubject<string>>;
+
+    /**
+     * @param device Device to invoke command
+     * @param command Command to execute
+     * @param outputEncoding
+     * @param pty Run the command in a terminal of this size, for programs that need one
+     * @param capture Also write the output to a file in the app's capture directory. The command keeps running when
+     * the page reloads, and can be found with {@link captures} then.
+     */
+    public async popen<T = Buffer | string>(device: DeviceLike, command: string, outputEncoding?: 'buffer' | 'utf-8',
+                                            pty?: ProcPty, capture?: CaptureOptions): Promise<CommandSubject<T>> {
+        // Nothing comes until the subject sends the start message
+        let subject: CommandSubject<T> | undefined;
+        const onOutput = outputChannel(frame => subject?.receive(frame), frames => subject?.ack(frames));
+        const token: string = await this.invoke('spawn', {device, command, pty, capture, onOutput});
+        subject = new CommandSubject<T>(this.zone, token, command, outputEncoding ?? 'buffer');
+        return subject;
+    }
+
+    /**
+     * Commands still writing output to a file
+     */
+    public async captures(): Promise<CaptureInfo[]> {
+        return this.invoke('captures');
+    }
+
+    public async stopCapture(id: string): Promise<void> {
+        await this.invoke('stop_capture', {id});
     }
 
     private static byteStringEncoding(encoding?: 'buffer' | 'utf-8') {
@@ -86,21 +196,21 @@ export declare class CommandData<T = Buffer | string> {
 }
 
 export class CommandSubject<T = Buffer | string> extends ReplaySubject<CommandData<T>> {
-    private channel: EventChannel<ProcData, SpawnResult>;
+    private channel: EventChannel<OutputFrame, SpawnResult>;
 
     constructor(zone: NgZone, token: string, command: string, encoding: 'buffer' | 'utf-8' | undefined) {
         super();
         const subject = this;
-        this.channel = new class extends EventChannel<ProcData, SpawnResult> {
+        this.channel = new class extends EventChannel<OutputFrame, SpawnResult> {
             stdout: string = '';
             stderr: string = '';
-            interrupted = false;
+            signalled = false;
 
             constructor(token: string) {
                 super(token);
             }
 
-            onReceive(payload: ProcData): void {
+            onReceive(payload: OutputFrame): void {
                 if (payload.fd !== 0) {
                     this.stderr += Buffer.from(payload.data).toString('utf-8');
                     return;
@@ -134,29 +244,27 @@ export class CommandSubject<T = Buffer | string> extends ReplaySubject<CommandDa
                         zone.run(() => subject.error(new ExecutionError(`Process exited with status ${payload.status}`,
                             payload.status, this.stderr, command)));
                     }
+                } else if (this.signalled) {
+                    // Treat stopping on a user initiated signal as success
+                    zone.run(() => subject.complete());
                 } else if (payload.type === 'Signal') {
-                    // Treat user initiated SIGINT as success
-                    if (this.interrupted && payload.signal === "INT") {
-                        zone.run(() => subject.complete());
-                    } else {
-                        zone.run(() => subject.error(new ExecutionError(`Process exited with signal ${payload.signal}`,
-                            -1, this.stderr, command)));
-                    }
+                    zone.run(() => subject.error(new ExecutionError(`Process exited with signal ${payload.signal}`,
+                        -1, this.stderr, command)));
                 } else {
                     zone.run(() => subject.error(new Error('Process closed')));
                 }
             }
 
             override async send<P>(payload?: P): Promise<void> {
-                if (isNil(payload)) {
-                    this.interrupted = true;
+                if ((payload as ProcMessage | undefined)?.type === 'signal') {
+                    this.signalled = true;
                 }
                 return super.send(payload);
             }
 
         }(token);
         // After creation, notify the process to start
-        this.channel.send().catch(noop);
+        this.send({type: 'start'}).catch(noop);
     }
 
     override complete() {
@@ -169,17 +277,68 @@ export class CommandSubject<T = Buffer | string> extends ReplaySubject<CommandDa
         this.channel.unlisten().catch(noop);
     }
 
-    async write(data?: Uint8Array): Promise<void> {
-        await this.channel.send({data});
+    async write(data: Uint8Array | string): Promise<void> {
+        await this.send({type: 'data', data: Array.from(Buffer.from(data))});
     }
 
-}
+    /**
+     * Closes stdin of the command, so commands reading it to the end can finish.
+     */
+    async end(): Promise<void> {
+        await this.send({type: 'eof'});
+    }
+
+    /**
+     * Resizes the terminal of a command started with a PTY.
+     */
+    async resize(rows: number, cols: number): Promise<void> {
+        await this.send({type: 'resize', rows, cols});
+    }
+
+    /**
+     * Sends a signal to the command.
+     *
+     * @param signal Signal name without `SIG`
+     * @param killAfter Seconds to wait before sending `KILL` if the command is still running
+     */
+    async kill(signal: ProcSignal = 'TERM', killAfter?: number): Promise<void> {
+        await this.send({type: 'signal', signal, killAfter});
+    }
+
+    /**
+     * Closes stdin and stops the command, with `KILL` if it doesn't exit on `TERM` in a few seconds.
+     */
+    async interrupt(): Promise<void> {
+        await this.end();
+        await this.kill('TERM', 3);
+    }
+
+    /**
+     * @internal
+     */
+    receive(frame: OutputFrame) {
+        if (!this.channel.closed) {
+            this.channel.onReceive(frame);
+        }
+    }
+
+    /**
+     * @internal
+     */
+    async ack(frames: number): Promise<void> {
+        await this.send({type: 'ack', frames}).catch(noop);
+    }
+
+    private send(message: ProcMessage): Promise<void> {
+        return this.channel.send(message);
+    }
 
-declare interface ProcData {
-    fd: number;
-    data: number[];
 }
 
+declare type ProcMessage = { type: 'start' } | { type: 'data', data: number[] } | { type: 'eof' }
+    | { type: 'signal', signal: ProcSignal, killAfter?: number } | { type: 'resize', rows: number, cols: number }
+    | { type: 'ack', frames: number };
+
 declare interface SpawnExited {
     type: 'Exit';
     status: number;
@@ -195,13 +354,98 @@ declare interface SpawnClosed {
     type: 'Closed';
 }
 
-declare interface ExecOutput<T> {
+export declare interface ExecOutput<T> {
     stdout: T;
     stderr: T;
+    /**
+     * Whether either stream was cut off at {@link ExecLimits.maxOutput}
+     */
+    truncated?: boolean;
+    result: SpawnResult;
+    /**
+     * Seconds the command ran for
+     */
+    duration: number;
 }
 
-type SpawnResult = SpawnExited | SpawnSignaled | SpawnClosed;
+export interface ExecManyResult<T> {
+    /**
+     * Name of the device
+     */
+    device: string;
+    output?: ExecOutput<T>;
+    error?: BackendErrorBody;
+}
+
+export interface ProcPty {
+    /**
+     * `TERM` of the terminal, `xterm` by default
+     */
+    term?: string;
+    rows: number;
+    cols: number;
+}
+
+export type ProcSignal = 'ABRT' | 'ALRM' | 'FPE' | 'HUP' | 'ILL' | 'INT' | 'KILL' | 'PIPE' | 'QUIT' | 'SEGV'
+    | 'TERM' | 'USR1' | 'USR2';
+
+export interface CaptureOptions {
+    /**
+     * Name of the file to append the output to, in the app's capture directory
+     */
+    name: string;
+    /**
+     * Bytes after which the file is moved to `name.1`, and older ones up by one
+     */
+    maxSize?: number;
+    /**
+     * Rotated files to keep, 5 by default
+     */
+    maxFiles?: number;
+    /**
+     * Start each line with the UTC time it was read
+     */
+    timestamps?: boolean;
+    /**
+     * Start each line with `out` or `err`
+     */
+    tagStreams?: boolean;
+}
+
+export interface CaptureInfo {
+    id: string;
+    device: string;
+    command: string;
+    /**
+     * Full path of the file in the app's capture directory
+     */
+    path: string;
+    bytesWritten: number;
+    /**
+     * Seconds since the capture started
+     */
+    age: number;
+}
 
-export function escapeSingleQuoteString(value: string) {
-    return value.split('\'').map(s => `'${s}'`).join('\\\'');
+export interface CommandLine {
+    argv: string[];
+    env?: Record<string, string>;
+    cwd?: string;
 }
+
+export interface ExecLimits {
+    /**
+     * Seconds before the command is killed and the call fails with a `Timeout` error
+     */
+    timeout?: number;
+    /**
+     * Bytes of each stream to keep
+     */
+    maxOutput?: number;
+    /**
+     * Resolve with {@link ExecOutput.result} instead of throwing {@link ExecutionError} when the command fails
+     */
+    allowFailure?: boolean;
+}
+
+type SpawnResult = SpawnExited | SpawnSignaled | SpawnClosed;
@@ -1,5 +1,5 @@
 import {Injectable} from "@angular/core";
-import {escapeSingleQuoteString, RemoteCommandService} from "./remote-command.service";
+import {RemoteCommandService} from "./remote-command.service";
 import {Device, DeviceLike} from "../../types";
 import {finalize, Observable} from "rxjs";
 import {filter, map} from "rxjs/operators";
@@ -19,14 +19,14 @@ export class RemoteLogService {
         const subject = await this.cmd.popen(device, `tail -f -n ${lastLines} /var/log/messages`, 'utf-8');
         return subject.pipe(map(output => this.parsePmLog(output.data)),
             filter((msg): msg is LogMessage => isNonNull(msg)),
-            finalize(() => subject.write()));
+            finalize(() => subject.interrupt()));
     }
 
     async dmesg(device: Device): Promise<Observable<LogMessage>> {
         const subject = await this.cmd.popen(device, `dmesg -w -x`, 'utf-8');
         return subject.pipe(map(output => this.parseDmesg(output.data)),
             filter((msg): msg is LogMessage => isNonNull(msg)),
-            finalize(() => subject.write()));
+            finalize(() => subject.interrupt()));
     }
 
     async logClear(device: DeviceLike): Promise<void> {
@@ -100,7 +100,7 @@ export class RemoteLogService {
     }
 
     async pmLogSetLevel(device: DeviceLike, context: string, value: PrefLogLevel): Promise<string[]> {
-        return await this.cmd.exec(device, `PmLogCtl set ${escapeSingleQuoteString(context)} ${value}`, 'utf-8').then((lines) => {
+        return await this.cmd.execArgv(device, {argv: ['PmLogCtl', 'set', context, value]}, 'utf-8').then((lines) => {
             return Array.from<RegExpMatchArray>(lines.matchAll(/^PmLogCtl: Setting context level for '([^']+)'/mg))
                 .map((m): string => m[1]);
         });
@@ -1,16 +1,9 @@
-import {
-    CommandData,
-    CommandSubject,
-    escapeSingleQuoteString,
-    ExecutionError,
-    RemoteCommandService
-} from "./remote-command.service";
-import {Injectable} from "@angular/core";
+import {Injectable, NgZone} from "@angular/core";
 import {DeviceLike} from "../../types";
-import {lastValueFrom, noop, Observable, ReplaySubject, Subject, Subscription} from "rxjs";
-import {filter, map} from "rxjs/operators";
+import {noop, Observable, ReplaySubject} from "rxjs";
 import {omit} from "lodash-es";
-import {isNonNull} from "../../shared/operators";
+import {BackendClient, BackendError, BackendErrorBody} from "./backend-client";
+import {EventChannel} from "../event-channel";
 
 export declare interface LunaResponse extends Record<string, any> {
     returnValue?: boolean,
@@ -20,93 +13,101 @@ export declare interface LunaResponse extends Record<string, any> {
 @Injectable({
     providedIn: 'root'
 })
-export class RemoteLunaService {
-    constructor(private commands: RemoteCommandService) {
+export class RemoteLunaService extends BackendClient {
+    constructor(zone: NgZone) {
+        super(zone, 'luna');
     }
 
     async call<T extends LunaResponse>(device: DeviceLike, uri: string, param: Record<string, unknown> = {}, pub: boolean = true,
                                        falseAsError: boolean = true): Promise<T> {
-        const sendCmd = pub ? 'luna-send-pub' : 'luna-send';
-        const nonce = self.crypto.randomUUID();
-        console.debug('remote-luna call', nonce, 'send', uri, param);
-        return this.commands.exec(device, `${sendCmd} -n 1 ${uri} ${escapeSingleQuoteString(JSON.stringify(param))}`, 'utf-8')
-            .catch(e => {
-                if (ExecutionError.isCompatible(e) && e.status == 127) {
-                    throw new LunaUnsupportedError(`Failed to find command ${sendCmd}. Is this really a webOS device?`);
-                }
-                throw e;
-            })
-            .then(out => {
-                let typed: T;
-                try {
-                    typed = JSON.parse(out.trim());
-                } catch (e) {
-                    console.warn('remote-luna call', nonce, 'invalid response: ', out);
-                    throw new Error(`Bad response ${out}`);
-                }
-                console.debug('remote-luna call', nonce, 'response', typed);
-                if (typed.returnValue === false) {
-                    const errorText: string | undefined = typed['errorText'];
-                    if (errorText?.startsWith('Unknown method')) {
-                        throw new LunaUnknownMethodError(typed);
-                    }
-                    if (errorText?.startsWith('Service does not exist')) {
-                        throw new LunaServiceNotFoundError(typed);
-                    }
-                    if (falseAsError) {
-                        throw new LunaResponseError(typed);
-                    }
-                }
-                return typed;
-            });
+        return this.invoke<T>('call', {device, uri, payload: param, public: pub}).catch(e => {
+            const response = RemoteLunaService.negativeResponse(e);
+            if (response && !falseAsError && !RemoteLunaService.responseError(response)) {
+                return response as T;
+            }
+            throw RemoteLunaService.mapError(e);
+        });
     }
 
     async subscribe<T extends LunaResponse>(device: DeviceLike, uri: string, param: Record<string, unknown> = {},
                                             pub: boolean = true): Promise<LunaSubscription<T>> {
-        const sendCmd = pub ? 'luna-send-pub' : 'luna-send';
-        const command = `${sendCmd} -i ${uri} ${escapeSingleQuoteString(JSON.stringify(param))}`;
-        const nonce = self.crypto.randomUUID();
-        try {
-            console.debug('remote-luna subscribe', nonce, 'send', uri, param);
-            const subject = await this.commands.popen(device, command, 'utf-8');
-            return new LunaSubscription<T>(subject, nonce);
-        } catch (e) {
-            if (ExecutionError.isCompatible(e) && e.status == 127) {
-                throw new LunaUnsupportedError(`Failed to find command ${sendCmd}. Is this really a webOS device?`);
-            }
-            throw e;
+        const token = await this.invoke<string>('subscribe', {device, uri, payload: param, public: pub})
+            .catch(e => {
+                throw RemoteLunaService.mapError(e);
+            });
+        return new LunaSubscription<T>(this.zone, token);
+    }
+
+    /**
+     * The response a Luna call failed with, as `luna-send` printed it.
+     */
+    private static negativeResponse(e: unknown): LunaResponse | undefined {
+        if (!BackendError.isCompatible(e) || e.reason !== 'Luna') {
+            return undefined;
+        }
+        return {returnValue: false, errorCode: e['error_code'], errorText: e['error_text']};
+    }
+
+    /**
+     * The error for a negative response, if it should be one regardless of `falseAsError`.
+     */
+    private static responseError(response: LunaResponse): LunaResponseError | undefined {
+        const errorText: string | undefined = response['errorText'];
+        if (errorText?.startsWith('Unknown method')) {
+            return new LunaUnknownMethodError(response);
+        }
+        if (errorText?.startsWith('Service does not exist')) {
+            return new LunaServiceNotFoundError(response);
+        }
+        return undefined;
+    }
+
+    /**
+     * Maps backend errors to the Luna errors callers handle.
+     */
+    static mapError(e: unknown): unknown {
+        if (BackendError.isCompatible(e) && e.reason === 'Unsupported') {
+            return new LunaUnsupportedError(`Failed to find luna-send command. Is this really a webOS device?`);
+        }
+        const response = RemoteLunaService.negativeResponse(e);
+        if (response) {
+            return RemoteLunaService.responseError(response) ?? new LunaResponseError(response);
         }
+        return e;
     }
 }
 
 export class LunaSubscription<T> {
-    private subject: Subject<T>;
-    private subscription?: Subscription;
-    private stderr = '';
-
-    constructor(private proc: CommandSubject<string>, private nonce: string) {
-        this.subject = new ReplaySubject<T>();
-        this.subscription = proc.pipe(map((v: CommandData<string>) => {
-            if (v.fd === 0) {
-                return JSON.parse(v.data.trim());
-            } else {
-                this.stderr += v.data;
-                return null;
+    private subject = new ReplaySubject<T>();
+    private channel: EventChannel<T, BackendErrorBody | null>;
+    private done?: Promise<void>;
+
+    constructor(zone: NgZone, token: string) {
+        const subject = this.subject;
+        let closed: () => void = noop;
+        const done = new Promise<void>(resolve => closed = resolve);
+        this.channel = new class extends EventChannel<T, BackendErrorBody | null> {
+            constructor() {
+                super(token);
+            }
+
+            onReceive(payload: T): void {
+                console.debug('remote-luna subscribe', token, 'response', payload);
+                zone.run(() => subject.next(payload));
             }
-        }), filter(isNonNull)).subscribe({
-            next: (value: T) => {
-                console.debug('remote-luna subscribe', this.nonce, 'response', value);
-                this.subject.next(value);
-            },
-            error: (err) => {
-                if (ExecutionError.isCompatible(err) && err.status == 127) {
-                    this.subject.error(new LunaUnsupportedError(`Failed to invoke luna-send command. Is this really a webOS device?`));
+
+            onClose(payload: BackendErrorBody | null): void {
+                if (payload) {
+                    zone.run(() => subject.error(RemoteLunaService.mapError(new BackendError(payload, 'luna/subscribe'))));
                 } else {
-                    this.subject.error(err);
+                    zone.run(() => subject.complete());
                 }
-            },
-            complete: () => this.subject.complete(),
-        });
+                this.unlisten().then(closed);
+            }
+        }();
+        this.done = done;
+        // After creation, notify the subscription to start
+        this.channel.send().catch(noop);
     }
 
     asObservable(): Observable<T> {
@@ -117,15 +118,16 @@ export class LunaSubscription<T> {
      * Multiple unsubscribe calls will not have side effect
      */
     async unsubscribe(): Promise<void> {
-        const subscription = this.subscription;
-        if (!subscription) {
+        const done = this.done;
+        if (!done) {
             return;
         }
-        this.subscription = undefined;
-        await this.proc.write();
+        this.done = undefined;
+        if (!this.channel.closed) {
+            await this.channel.close();
+        }
         this.subject.complete();
-        await lastValueFrom(this.proc).catch(noop);
-        subscription.unsubscribe();
+        await done;
     }
 }
 
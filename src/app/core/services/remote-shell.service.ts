@@ -4,6 +4,7 @@ import {BackendClient, BackendErrorBody} from "./backend-client";
 import {Injectable, NgZone} from "@angular/core";
 import {Device} from "../../types";
 import {Buffer} from "buffer";
+import {OutputFrame, outputChannel} from "./output-frames";
 
 
 export type ShellToken = string;
@@ -18,15 +19,10 @@ export interface ShellInfo {
 export type ShellState =
   { which: 'Connecting' } |
   { which: 'Connected' } |
+  { which: 'Reconnecting'; attempt: number; } |
   { which: 'Exited'; returnCode: number; } |
   { which: 'Error'; error: BackendErrorBody; };
 
-export interface ShellMessage {
-  token: ShellToken;
-  fd: number;
-  data: number[];
-}
-
 export interface ShellScreenContent {
   rows?: Uint8Array[];
   data?: Uint8Array;
@@ -81,16 +77,6 @@ export class RemoteShellService extends BackendClient {
   constructor(zone: NgZone) {
     super(zone, 'remote-shell');
     this.shellsSubject = new BehaviorSubject<ShellInfo[] | null>(null);
-    listen('shell-rx', (e: Event<ShellMessage>) => {
-      const message = e.payload;
-      const shell = this.shellSessions.get(message.token);
-      if (shell) {
-        zone.run(() => shell.next({
-          fd: message.fd,
-          data: Buffer.from(message.data),
-        }));
-      }
-    }).then(noop);
     listen('shell-info', () => {
       this.list().then(l => this.shellsSubject.next(l));
     }).then(noop);
@@ -98,10 +84,48 @@ export class RemoteShellService extends BackendClient {
       console.log('shell-opened', this.shellSessions, e.payload);
       this.obtain(e.payload);
     }).then(noop);
+    // Shells outlive a reload of the page, but their output still goes to the channel of the page before
+    this.list().then(shells => Promise.all(shells.map(shell => this.attach(shell.token)))).catch(noop);
+  }
+
+  /**
+   * @param reconnect Keep retrying with the same screen when the connection drops, instead of ending with an error
+   */
+  async open(device: Device, rows: number, cols: number, dumb?: boolean, reconnect?: boolean): Promise<ShellInfo> {
+    // Output may come before the token is known, so it waits here until then
+    let token: ShellToken | undefined;
+    const early: OutputFrame[] = [];
+    let handled = 0;
+    const onOutput = outputChannel(frame => {
+      if (token) {
+        this.output(token, frame);
+      } else {
+        early.push(frame);
+      }
+    }, frames => {
+      handled = frames;
+      if (token) {
+        this.ack(token, frames).catch(noop);
+      }
+    });
+    const info: ShellInfo = await this.invoke('open', {device, rows, cols, dumb, reconnect, onOutput});
+    token = info.token;
+    for (const frame of early.splice(0)) {
+      this.output(token, frame);
+    }
+    if (handled) {
+      this.ack(token, handled).catch(noop);
+    }
+    return info;
   }
 
-  async open(device: Device, rows: number, cols: number, dumb?: boolean): Promise<ShellInfo> {
-    return this.invoke('open', {device, rows, cols, dumb});
+  /**
+   * Takes over the output of a shell opened before the page reloaded.
+   */
+  async attach(token: ShellToken): Promise<ShellInfo> {
+    const onOutput = outputChannel(frame => this.output(token, frame),
+      frames => this.ack(token, frames).catch(noop));
+    return this.invoke('attach', {token, onOutput});
   }
 
   async close(token: ShellToken): Promise<void> {
@@ -120,10 +144,19 @@ export class RemoteShellService extends BackendClient {
     await this.invoke('write', {token, data});
   }
 
+  async ack(token: ShellToken, frames: number): Promise<void> {
+    await this.invoke('ack', {token, frames});
+  }
+
   async resize(token: ShellToken, rows: number, cols: number): Promise<void> {
     await this.invoke('resize', {token, rows, cols});
   }
 
+  private output(token: ShellToken, frame: OutputFrame): void {
+    const shell = this.obtain(token) as ShellSubject;
+    this.zone.run(() => shell.next({fd: frame.fd, data: Buffer.from(frame.data)}));
+  }
+
   obtain(token: ShellToken): ShellObservable {
     let shell = this.shellSessions.get(token);
     if (!shell) {
@@ -93,7 +93,7 @@ export class LsMonitorComponent implements OnDestroy {
             map(line => JSON.parse(line.data) as MonitorMessage),
             finalize(() => {
                 this.isCapturing = false;
-                proc.write();
+                proc.interrupt();
             })
         );
         this.reload(this.messages);
@@ -118,7 +118,7 @@ export class TerminalComponent implements OnInit, OnDestroy {
         const startWith = device ?? await firstValueFrom(this.deviceManager.selected$.pipe<Device>(filter(isNonNull)));
         const progress = ProgressDialogComponent.open(this.modals);
         try {
-            const shellInfo = await this.shell.open(startWith, size.rows, size.cols, this.preferDumbShell);
+            const shellInfo = await this.shell.open(startWith, size.rows, size.cols, this.preferDumbShell, true);
             this.shells.push(shellInfo);
             this.currentShell = shellInfo.token;
         } catch (e) {